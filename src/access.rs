@@ -0,0 +1,212 @@
+use std::{collections::HashSet, net::IpAddr};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha2::Sha256;
+use ulid::Ulid;
+
+use crate::error::Error;
+
+type InviteMac = Hmac<Sha256>;
+
+/// Restrictions applied to participants looking up or joining a room.
+#[derive(Debug)]
+pub struct Access {
+    password: Option<Box<str>>,
+    invite_only: bool,
+    invite_key: [u8; 32],
+    revoked_invites: HashSet<Ulid>,
+    banned_sessions: HashSet<Box<str>>,
+    banned_ips: HashSet<IpAddr>,
+}
+
+impl Access {
+    pub fn new(password: Option<Box<str>>, invite_only: bool) -> Self {
+        Self {
+            password: password.filter(|p| !p.is_empty()),
+            invite_only,
+            invite_key: rand::thread_rng().gen(),
+            revoked_invites: HashSet::new(),
+            banned_sessions: HashSet::new(),
+            banned_ips: HashSet::new(),
+        }
+    }
+
+    pub fn password_protected(&self) -> bool {
+        self.password.is_some()
+    }
+
+    pub fn invite_only(&self) -> bool {
+        self.invite_only
+    }
+
+    /// A valid invite bypasses both the password and the invite only
+    /// restrictions.
+    pub fn check(&self, password: Option<&str>, invite: Option<&str>) -> Result<(), Error> {
+        if let Some(invite) = invite {
+            return self.verify_invite(invite).map(|_| ());
+        }
+        if self.invite_only {
+            return Err(Error::RoomInviteRequired);
+        }
+        match (&self.password, password) {
+            (None, _) => Ok(()),
+            (Some(_), None) => Err(Error::RoomPasswordRequired),
+            (Some(expected), Some(password)) if **expected == *password => Ok(()),
+            (Some(_), Some(_)) => Err(Error::RoomPasswordInvalid),
+        }
+    }
+
+    pub fn create_invite(&self) -> Box<str> {
+        let id = Ulid::new();
+        format!(
+            "{id}.{}",
+            URL_SAFE_NO_PAD.encode(self.sign(id).finalize().into_bytes())
+        )
+        .into_boxed_str()
+    }
+
+    pub fn revoke_invite(&mut self, invite: &str) -> Result<(), Error> {
+        let id = self.verify_invite(invite)?;
+        self.revoked_invites.insert(id);
+        Ok(())
+    }
+
+    pub fn ban(&mut self, session: Box<str>, ip: Option<IpAddr>) {
+        self.banned_sessions.insert(session);
+        self.banned_ips.extend(ip);
+    }
+
+    pub fn check_ban(&self, session: Option<&str>, ip: IpAddr) -> Result<(), Error> {
+        if session.is_some_and(|s| self.banned_sessions.contains(s))
+            || self.banned_ips.contains(&ip)
+        {
+            return Err(Error::ParticipantBanned);
+        }
+        Ok(())
+    }
+
+    fn verify_invite(&self, invite: &str) -> Result<Ulid, Error> {
+        let (id, signature) = invite.split_once('.').ok_or(Error::RoomInviteInvalid)?;
+        let id = Ulid::from_string(id).map_err(|_| Error::RoomInviteInvalid)?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| Error::RoomInviteInvalid)?;
+        self.sign(id)
+            .verify_slice(&signature)
+            .map_err(|_| Error::RoomInviteInvalid)?;
+        if self.revoked_invites.contains(&id) {
+            return Err(Error::RoomInviteInvalid);
+        }
+        Ok(id)
+    }
+
+    fn sign(&self, id: Ulid) -> InviteMac {
+        let mut mac = InviteMac::new_from_slice(&self.invite_key).expect("invalid key length");
+        mac.update(&id.0.to_be_bytes());
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invite_is_accepted() {
+        let access = Access::new(None, true);
+        let invite = access.create_invite();
+        assert!(access.check(None, Some(&invite)).is_ok());
+    }
+
+    #[test]
+    fn invite_bypasses_password() {
+        let access = Access::new(Some("secret".into()), false);
+        let invite = access.create_invite();
+        assert!(access.check(None, Some(&invite)).is_ok());
+        assert!(access.check(Some("wrong"), Some(&invite)).is_ok());
+    }
+
+    #[test]
+    fn invite_of_another_room_is_rejected() {
+        let access = Access::new(None, true);
+        let invite = Access::new(None, true).create_invite();
+        assert!(matches!(
+            access.check(None, Some(&invite)),
+            Err(Error::RoomInviteInvalid)
+        ));
+    }
+
+    #[test]
+    fn tampered_invite_is_rejected() {
+        let access = Access::new(None, true);
+        let invite = access.create_invite();
+        let (_, signature) = invite.split_once('.').unwrap();
+        let forged = format!("{}.{signature}", Ulid::new());
+        for invite in [
+            &*forged,
+            "",
+            "garbage",
+            "01H.AAAA",
+            &invite[..invite.len() - 1],
+        ] {
+            assert!(matches!(
+                access.check(None, Some(invite)),
+                Err(Error::RoomInviteInvalid)
+            ));
+        }
+    }
+
+    #[test]
+    fn revoked_invite_is_rejected() {
+        let mut access = Access::new(None, true);
+        let revoked = access.create_invite();
+        let kept = access.create_invite();
+        access.revoke_invite(&revoked).unwrap();
+        assert!(matches!(
+            access.check(None, Some(&revoked)),
+            Err(Error::RoomInviteInvalid)
+        ));
+        assert!(access.check(None, Some(&kept)).is_ok());
+    }
+
+    #[test]
+    fn revoking_an_invalid_invite_fails() {
+        let mut access = Access::new(None, true);
+        assert!(matches!(
+            access.revoke_invite("garbage"),
+            Err(Error::RoomInviteInvalid)
+        ));
+    }
+
+    #[test]
+    fn invite_only_requires_invite() {
+        let access = Access::new(Some("secret".into()), true);
+        assert!(matches!(
+            access.check(Some("secret"), None),
+            Err(Error::RoomInviteRequired)
+        ));
+    }
+
+    #[test]
+    fn password_is_checked() {
+        let access = Access::new(Some("secret".into()), false);
+        assert!(matches!(
+            access.check(None, None),
+            Err(Error::RoomPasswordRequired)
+        ));
+        assert!(matches!(
+            access.check(Some("wrong"), None),
+            Err(Error::RoomPasswordInvalid)
+        ));
+        assert!(access.check(Some("secret"), None).is_ok());
+    }
+
+    #[test]
+    fn empty_password_is_ignored() {
+        let access = Access::new(Some("".into()), false);
+        assert!(!access.password_protected());
+        assert!(access.check(None, None).is_ok());
+    }
+}
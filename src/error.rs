@@ -6,7 +6,7 @@ use axum::{
 use serde_json::json;
 use thiserror::Error as ThisError;
 
-#[derive(ThisError, Copy, Clone, Debug)]
+#[derive(ThisError, Clone, Debug)]
 pub enum Error {
     #[error("Room not found")]
     RoomNotFound,
@@ -14,29 +14,65 @@ pub enum Error {
     RoomAlreadyExist,
     #[error("Room name too short")]
     RoomNameTooShort,
+    #[error("Room name too long")]
+    RoomNameTooLong,
+    #[error("Room password required")]
+    RoomPasswordRequired,
+    #[error("Invalid room password")]
+    RoomPasswordInvalid,
+    #[error("Room invite required")]
+    RoomInviteRequired,
+    #[error("Invalid room invite")]
+    RoomInviteInvalid,
     #[error("Username name too short")]
     UsernameTooShort,
+    #[error("Username too long")]
+    UsernameTooLong,
+    #[error("Username already taken")]
+    UsernameTaken { suggestion: Box<str> },
+    #[error("Participant not found")]
+    ParticipantNotFound,
+    #[error("Banned from the room")]
+    ParticipantBanned,
+    #[error("Host secret required")]
+    HostSecretRequired,
+    #[error("Invalid host secret")]
+    HostSecretInvalid,
+    #[error("Join link too long for a QR code")]
+    QrCodeDataTooLong,
 }
 
-impl From<Error> for StatusCode {
-    fn from(value: Error) -> Self {
+impl From<&Error> for StatusCode {
+    fn from(value: &Error) -> Self {
         match value {
             Error::RoomNotFound => StatusCode::NOT_FOUND,
             Error::RoomAlreadyExist => StatusCode::CONFLICT,
             Error::RoomNameTooShort => StatusCode::BAD_REQUEST,
+            Error::RoomNameTooLong => StatusCode::BAD_REQUEST,
+            Error::RoomPasswordRequired => StatusCode::UNAUTHORIZED,
+            Error::RoomPasswordInvalid => StatusCode::FORBIDDEN,
+            Error::RoomInviteRequired => StatusCode::FORBIDDEN,
+            Error::RoomInviteInvalid => StatusCode::FORBIDDEN,
             Error::UsernameTooShort => StatusCode::BAD_REQUEST,
+            Error::UsernameTooLong => StatusCode::BAD_REQUEST,
+            Error::UsernameTaken { .. } => StatusCode::CONFLICT,
+            Error::ParticipantNotFound => StatusCode::NOT_FOUND,
+            Error::ParticipantBanned => StatusCode::FORBIDDEN,
+            Error::HostSecretRequired => StatusCode::UNAUTHORIZED,
+            Error::HostSecretInvalid => StatusCode::FORBIDDEN,
+            Error::QrCodeDataTooLong => StatusCode::BAD_REQUEST,
         }
     }
 }
 
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
-        (
-            StatusCode::from(self),
-            Json(json!({
-                "error": self.to_string(),
-            })),
-        )
-            .into_response()
+        let mut body = json!({
+            "error": self.to_string(),
+        });
+        if let Error::UsernameTaken { suggestion } = &self {
+            body["suggestion"] = json!(suggestion);
+        }
+        (StatusCode::from(&self), Json(body)).into_response()
     }
 }
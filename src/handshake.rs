@@ -0,0 +1,127 @@
+use std::{collections::HashSet, time::Duration};
+
+use axum::extract::ws::{Message as WsMessage, WebSocket};
+use log::{as_debug, warn};
+use tokio::time;
+
+use crate::{
+    packet::{ErrorCode, PacketIn, PacketOut, Settings},
+    room::RoomConfig,
+};
+
+/// Version of the packets exchanged over WebSockets, bumped on breaking changes.
+pub const PROTOCOL_VERSION: u32 = 1;
+// Oldest client version still understood by the server.
+const MIN_PROTOCOL_VERSION: u32 = 1;
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Answering WebSocket pings in time, required when the heartbeat is enabled.
+pub const HEARTBEAT: &str = "heartbeat";
+// Reporting the time elapsed between the round start marker and the buzz.
+pub const REPORTED_TIME: &str = "reportedTime";
+
+/// Capabilities declared by a client in its hello.
+#[derive(Debug)]
+pub struct Capabilities(HashSet<Box<str>>);
+
+impl Capabilities {
+    pub fn contains(&self, capability: &str) -> bool {
+        self.0.contains(capability)
+    }
+}
+
+/// Features enabled by the configuration of the server.
+fn features(config: &RoomConfig) -> Vec<&'static str> {
+    let heartbeat = !config.heartbeat_interval.is_zero();
+    let mut features = vec!["cohosts", "spectators", "teams", "scoreboard", "timer"];
+    if !config.false_start_penalty.is_zero() {
+        features.push("falseStartPenalty");
+    }
+    if heartbeat {
+        // Round trip times are measured by the heartbeat.
+        features.extend([HEARTBEAT, "latencyCompensation"]);
+    }
+    if heartbeat && !config.settling_window.is_zero() {
+        features.push(REPORTED_TIME);
+    }
+    features
+}
+
+/// Capabilities a client must have to connect to a room using the given
+/// configuration.
+fn required_capabilities(config: &RoomConfig) -> Vec<&'static str> {
+    let mut capabilities = Vec::new();
+    if !config.heartbeat_interval.is_zero() {
+        capabilities.push(HEARTBEAT);
+    }
+    capabilities
+}
+
+/// Waits for the hello of the client and answers it with the server version,
+/// features and room settings, returning the capabilities of the client.
+/// Incompatible clients get an error and are disconnected.
+pub async fn handshake(
+    socket: &mut WebSocket,
+    config: RoomConfig,
+    settings: Settings,
+) -> Option<Capabilities> {
+    let hello = match time::timeout(HELLO_TIMEOUT, socket.recv()).await {
+        Ok(Some(Ok(msg))) => match PacketIn::try_from(msg) {
+            Ok(PacketIn::Hello {
+                version,
+                capabilities,
+            }) => Some((version, Capabilities(capabilities.into_iter().collect()))),
+            Ok(_) | Err(_) => None,
+        },
+        Ok(Some(Err(_)) | None) => return None,
+        Err(_) => None,
+    };
+
+    let (code, message) = match hello {
+        Some((version, capabilities))
+            if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) =>
+        {
+            warn!(version = version, capabilities = as_debug!(capabilities); "incompatible client");
+            (
+                ErrorCode::UnsupportedVersion,
+                format!(
+                    "protocol version {version} isn't supported, the server supports versions {MIN_PROTOCOL_VERSION} to {PROTOCOL_VERSION}, please reload the page"
+                ),
+            )
+        }
+        Some((version, capabilities)) => {
+            let missing = required_capabilities(&config)
+                .into_iter()
+                .filter(|c| !capabilities.contains(c))
+                .collect::<Vec<_>>();
+            if missing.is_empty() {
+                return socket
+                    .send(WsMessage::from(PacketOut::Hello {
+                        version: PROTOCOL_VERSION,
+                        features: features(&config),
+                        settings,
+                    }))
+                    .await
+                    .is_ok()
+                    .then_some(capabilities);
+            }
+            warn!(version = version, capabilities = as_debug!(capabilities); "client missing capabilities");
+            (
+                ErrorCode::MissingCapability,
+                format!("the room requires the {} capabilities", missing.join(", ")),
+            )
+        }
+        None => (
+            ErrorCode::HandshakeRequired,
+            "the first packet must be a hello, please reload the page".to_owned(),
+        ),
+    };
+    _ = socket
+        .send(WsMessage::from(PacketOut::Error {
+            code,
+            message: message.into_boxed_str(),
+        }))
+        .await;
+    _ = socket.send(WsMessage::Close(None)).await;
+    None
+}
@@ -0,0 +1,51 @@
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::request::Parts,
+};
+
+/// Whether the `X-Forwarded-For` header set by a reverse proxy can be trusted.
+#[derive(Clone, Copy)]
+pub struct TrustProxy(pub bool);
+
+/// Address of the client, used to ban participants.
+///
+/// Behind a trusted reverse proxy, the last address of the `X-Forwarded-For`
+/// header is used, as it is the one appended by the proxy. Otherwise, every
+/// client would share the address of the proxy.
+pub struct ClientIp(pub IpAddr);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let ConnectInfo(address) = *parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .expect("missing connect info");
+        let trust_proxy = parts
+            .extensions
+            .get::<TrustProxy>()
+            .is_some_and(|trust| trust.0);
+        if !trust_proxy {
+            return Ok(Self(address.ip()));
+        }
+        Ok(Self(
+            parts
+                .headers
+                .get_all("x-forwarded-for")
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .last()
+                .and_then(|ip| ip.trim().parse().ok())
+                .unwrap_or(address.ip()),
+        ))
+    }
+}
@@ -1,30 +1,50 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{convert::Infallible, net::SocketAddr, sync::Arc, time::Duration};
 
 use axum::{
-    extract::{Path, Query, State, WebSocketUpgrade},
-    http::{header, HeaderValue, StatusCode},
-    response::IntoResponse,
-    routing::{get, post},
-    Json, Router,
+    extract::{
+        ws::{Message as WsMessage, WebSocket},
+        Path, Query, State, WebSocketUpgrade,
+    },
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
+    routing::{delete, get, post},
+    Extension, Json, Router,
 };
 use clap::Parser;
+use futures::{stream, Stream};
 use serde::Deserialize;
 use serde_json::json;
-use tokio::sync::Mutex;
+use tokio::{sync::Mutex, time};
 use tower_http::set_header::SetResponseHeaderLayer;
 use ulid::Ulid;
 
-use crate::{error::Error, options::Options, registry::Registry};
+use crate::{
+    access::Access,
+    error::Error,
+    handshake::handshake,
+    ip::{ClientIp, TrustProxy},
+    options::Options,
+    packet::{ErrorCode, PacketOut},
+    registry::Registry,
+    room::{sanitize_username, HostRole},
+};
 
+mod access;
 mod asset;
 mod error;
+mod handshake;
+mod ip;
 mod options;
 mod packet;
+mod qr;
 mod registry;
 mod room;
 mod utils;
 
-const USERNAME_MIN_LEN: usize = 2;
+const DIRECTORY_REFRESH: Duration = Duration::from_secs(2);
 
 #[tokio::main]
 async fn main() {
@@ -35,27 +55,42 @@ async fn main() {
     log_panics::init();
 
     let router = Router::new()
-        .route("/rooms", post(reserve_room))
+        .route("/rooms", get(list_public_rooms).post(reserve_room))
+        .route("/rooms/live", get(watch_public_rooms))
         .route("/rooms/id", get(find_room_by_name))
+        .route("/rooms/code", get(find_room_by_code))
         .route("/rooms/:id/host", get(host_room))
+        .route("/rooms/:id/cohosts", post(create_cohost))
+        .route("/rooms/:id/invites", post(create_invite))
+        .route("/rooms/:id/invites/:invite", delete(revoke_invite))
         .route("/rooms/:id/participate", get(join_room))
-        .with_state(Arc::new(Mutex::new(Registry::default())))
+        .route("/rooms/:id/spectate", get(spectate_room))
+        .route("/rooms/:id/qr.svg", get(qr_code_svg))
+        .route("/rooms/:id/qr.png", get(qr_code_png))
+        .with_state(Arc::new(Mutex::new(Registry::new(options.room_config()))))
         .route("/", get(asset::handler))
         .route("/:asset", get(asset::handler))
+        .layer(Extension(TrustProxy(options.trust_proxy)))
         .layer(SetResponseHeaderLayer::overriding(
             header::SERVER,
             HeaderValue::from_static(concat!("Buzzer v", env!("CARGO_PKG_VERSION"))),
         ));
 
     axum::Server::bind(&SocketAddr::new(options.address, options.port))
-        .serve(router.into_make_service())
+        .serve(router.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .unwrap();
 }
 
 #[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct ReserveRoom {
     name: String,
+    password: Option<String>,
+    #[serde(default)]
+    invite_only: bool,
+    #[serde(default)]
+    public: bool,
 }
 
 async fn reserve_room(
@@ -63,41 +98,137 @@ async fn reserve_room(
     Json(request): Json<ReserveRoom>,
 ) -> Result<impl IntoResponse, Error> {
     let weak_registry = Arc::downgrade(&registry);
-    let (id, name) = registry
+    let (id, name, secret, code) = registry
         .lock()
         .await
-        .reserve(&request.name, weak_registry)
+        .reserve(
+            &request.name,
+            Access::new(
+                request.password.map(String::into_boxed_str),
+                request.invite_only,
+            ),
+            request.public,
+            weak_registry,
+        )
         .await?;
     Ok((
         StatusCode::CREATED,
         Json(json!({
             "id": id,
             "name": name,
+            "secret": secret,
+            "code": code,
         })),
     ))
 }
 
+async fn list_public_rooms(State(registry): State<Arc<Mutex<Registry>>>) -> impl IntoResponse {
+    Json(registry.lock().await.public_rooms())
+}
+
+/// Streams the public rooms periodically, for lobby screens.
+async fn watch_public_rooms(
+    State(registry): State<Arc<Mutex<Registry>>>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = stream::unfold(
+        (registry, time::interval(DIRECTORY_REFRESH)),
+        |(registry, mut interval)| async move {
+            interval.tick().await;
+            let event = Event::default()
+                .json_data(registry.lock().await.public_rooms())
+                .expect("serialization failed");
+            Some((Ok(event), (registry, interval)))
+        },
+    );
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+#[derive(Deserialize)]
+struct HostRoomQuery {
+    secret: Option<String>,
+}
+
 async fn host_room(
     State(registry): State<Arc<Mutex<Registry>>>,
     Path(id): Path<Ulid>,
+    Query(HostRoomQuery { secret }): Query<HostRoomQuery>,
     ws: WebSocketUpgrade,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| async move {
+) -> Result<impl IntoResponse, Error> {
+    let secret = secret.ok_or(Error::HostSecretRequired)?;
+    let (config, settings) = {
+        let registry = registry.lock().await;
+        registry.check_host_connect(id, &secret)?;
+        (registry.room_config(), registry.settings(id)?)
+    };
+    Ok(ws.on_upgrade(move |mut socket| async move {
+        if handshake(&mut socket, config, settings).await.is_none() {
+            return;
+        }
         let weak_registry = Arc::downgrade(&registry);
-        let _ = registry.lock().await.create(id, socket, weak_registry);
-    })
+        let result = registry
+            .lock()
+            .await
+            .host(id, &secret, socket, weak_registry);
+        if let Err((err, socket)) = result {
+            refuse(*socket, err).await;
+        }
+    }))
 }
 
 #[derive(Deserialize)]
 struct FindRoomByNameQuery {
     name: String,
+    // Checked as well when provided, to detect duplicates before joining.
+    username: Option<String>,
+    password: Option<String>,
+    invite: Option<String>,
 }
 
 async fn find_room_by_name(
     State(registry): State<Arc<Mutex<Registry>>>,
-    Query(FindRoomByNameQuery { name }): Query<FindRoomByNameQuery>,
+    Query(FindRoomByNameQuery {
+        name,
+        username,
+        password,
+        invite,
+    }): Query<FindRoomByNameQuery>,
+) -> Result<impl IntoResponse, Error> {
+    let registry = registry.lock().await;
+    let (id, name) = registry.find_room(&name, password.as_deref(), invite.as_deref())?;
+    if let Some(username) = username {
+        registry.check_username(id, &username, None)?;
+    }
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "id": id,
+            "name": name,
+        })),
+    ))
+}
+
+#[derive(Deserialize)]
+struct FindRoomByCodeQuery {
+    code: String,
+    username: Option<String>,
+    password: Option<String>,
+    invite: Option<String>,
+}
+
+async fn find_room_by_code(
+    State(registry): State<Arc<Mutex<Registry>>>,
+    Query(FindRoomByCodeQuery {
+        code,
+        username,
+        password,
+        invite,
+    }): Query<FindRoomByCodeQuery>,
 ) -> Result<impl IntoResponse, Error> {
-    let (id, name) = registry.lock().await.find_room(&name)?;
+    let registry = registry.lock().await;
+    let (id, name) = registry.find_room_by_code(&code, password.as_deref(), invite.as_deref())?;
+    if let Some(username) = username {
+        registry.check_username(id, &username, None)?;
+    }
     Ok((
         StatusCode::OK,
         Json(json!({
@@ -110,19 +241,183 @@ async fn find_room_by_name(
 #[derive(Deserialize)]
 struct JoinRoomQuery {
     name: String,
+    session: Option<String>,
+    team: Option<String>,
+    password: Option<String>,
+    invite: Option<String>,
 }
 
 async fn join_room(
     State(registry): State<Arc<Mutex<Registry>>>,
     Path(id): Path<Ulid>,
-    Query(JoinRoomQuery { name }): Query<JoinRoomQuery>,
+    Query(JoinRoomQuery {
+        name,
+        session,
+        team,
+        password,
+        invite,
+    }): Query<JoinRoomQuery>,
+    ClientIp(ip): ClientIp,
     ws: WebSocketUpgrade,
-) -> impl IntoResponse {
-    let name = utils::sanitize(&name).to_owned().into_boxed_str();
-    if name.len() < USERNAME_MIN_LEN {
-        return Err(Error::UsernameTooShort);
-    }
-    Ok(ws.on_upgrade(move |socket| async move {
-        let _ = registry.lock().await.join_room(id, socket, name);
+) -> Result<impl IntoResponse, Error> {
+    let name = sanitize_username(&name)?;
+    let team = team
+        .as_deref()
+        .map(utils::sanitize)
+        .filter(|t| !t.is_empty());
+    let (config, settings) = {
+        let registry = registry.lock().await;
+        registry.check_access(id, password.as_deref(), invite.as_deref())?;
+        registry.check_ban(id, session.as_deref(), ip)?;
+        registry.check_username(id, &name, session.as_deref())?;
+        (registry.room_config(), registry.settings(id)?)
+    };
+    Ok(ws.on_upgrade(move |mut socket| async move {
+        let Some(capabilities) = handshake(&mut socket, config, settings).await else {
+            return;
+        };
+        let result = registry.lock().await.join_room(
+            id,
+            socket,
+            name,
+            session.map(String::into_boxed_str),
+            team,
+            ip,
+            capabilities,
+        );
+        if let Err((err, socket)) = result {
+            refuse(*socket, err).await;
+        }
+    }))
+}
+
+#[derive(Deserialize)]
+struct SpectateRoomQuery {
+    password: Option<String>,
+    invite: Option<String>,
+}
+
+async fn spectate_room(
+    State(registry): State<Arc<Mutex<Registry>>>,
+    Path(id): Path<Ulid>,
+    Query(SpectateRoomQuery { password, invite }): Query<SpectateRoomQuery>,
+    ClientIp(ip): ClientIp,
+    ws: WebSocketUpgrade,
+) -> Result<impl IntoResponse, Error> {
+    let (config, settings) = {
+        let registry = registry.lock().await;
+        registry.check_access(id, password.as_deref(), invite.as_deref())?;
+        registry.check_ban(id, None, ip)?;
+        (registry.room_config(), registry.settings(id)?)
+    };
+    Ok(ws.on_upgrade(move |mut socket| async move {
+        if handshake(&mut socket, config, settings).await.is_none() {
+            return;
+        }
+        let result = registry.lock().await.spectate(id, socket);
+        if let Err((err, socket)) = result {
+            refuse(*socket, err).await;
+        }
     }))
 }
+
+async fn create_invite(
+    State(registry): State<Arc<Mutex<Registry>>>,
+    Path(id): Path<Ulid>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, Error> {
+    let registry = registry.lock().await;
+    registry.check_host_secret(id, host_secret(&headers)?)?;
+    let invite = registry.create_invite(id)?;
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "invite": invite,
+        })),
+    ))
+}
+
+/// Gives the secret allowing other hosts to join the room. Only the owner of the
+/// room can share it.
+async fn create_cohost(
+    State(registry): State<Arc<Mutex<Registry>>>,
+    Path(id): Path<Ulid>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, Error> {
+    let registry = registry.lock().await;
+    if !matches!(
+        registry.check_host_secret(id, host_secret(&headers)?)?,
+        HostRole::Owner
+    ) {
+        return Err(Error::HostSecretInvalid);
+    }
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "secret": registry.cohost_secret(id)?,
+        })),
+    ))
+}
+
+async fn revoke_invite(
+    State(registry): State<Arc<Mutex<Registry>>>,
+    Path((id, invite)): Path<(Ulid, String)>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, Error> {
+    let mut registry = registry.lock().await;
+    registry.check_host_secret(id, host_secret(&headers)?)?;
+    registry.revoke_invite(id, &invite)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn qr_code_svg(
+    State(registry): State<Arc<Mutex<Registry>>>,
+    Path(id): Path<Ulid>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, Error> {
+    let link = join_link(&registry.lock().await.room_name(id)?, &headers);
+    Ok(([(header::CONTENT_TYPE, "image/svg+xml")], qr::svg(&link)?))
+}
+
+async fn qr_code_png(
+    State(registry): State<Arc<Mutex<Registry>>>,
+    Path(id): Path<Ulid>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, Error> {
+    let link = join_link(&registry.lock().await.room_name(id)?, &headers);
+    Ok(([(header::CONTENT_TYPE, "image/png")], qr::png(&link)?))
+}
+
+/// Tells the client why its connection was refused before closing it.
+async fn refuse(mut socket: WebSocket, err: Error) {
+    _ = socket
+        .send(WsMessage::from(PacketOut::Error {
+            code: ErrorCode::JoinRefused,
+            message: err.to_string().into_boxed_str(),
+        }))
+        .await;
+    _ = socket.send(WsMessage::Close(None)).await;
+}
+
+/// Builds the link prefilling the room name in the lobby, using the origin the
+/// request was sent to.
+fn join_link(room: &str, headers: &HeaderMap) -> String {
+    let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+    let scheme = header("x-forwarded-proto").unwrap_or("http");
+    let host = header("x-forwarded-host")
+        .or_else(|| header(header::HOST.as_str()))
+        .unwrap_or("localhost");
+    format!(
+        "{scheme}://{host}/?{}",
+        serde_urlencoded::to_string([("room", room)]).expect("invalid query")
+    )
+}
+
+/// Extracts the host secret from a `Authorization: Bearer <secret>` header.
+fn host_secret(headers: &HeaderMap) -> Result<&str, Error> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(Error::HostSecretRequired)
+}
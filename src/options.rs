@@ -1,8 +1,10 @@
-use std::net::IpAddr;
+use std::{net::IpAddr, time::Duration};
 
 use clap::{ArgAction, Parser};
 use log::LevelFilter;
 
+use crate::room::RoomConfig;
+
 #[derive(Parser, Debug)]
 #[command(version, about)]
 pub struct Options {
@@ -15,6 +17,31 @@ pub struct Options {
     /// HTTP listening port.
     #[arg(short = 'p', long, default_value = "8080")]
     pub port: u16,
+    /// Seconds to wait for a disconnected host to reconnect before closing the
+    /// room.
+    #[arg(short = 'g', long, default_value = "30")]
+    pub host_grace_period: u64,
+    /// Seconds during which participants buzzing while the buzzer is locked
+    /// can't buzz again (0 to disable).
+    #[arg(short = 'f', long, default_value = "0")]
+    pub false_start_penalty: u64,
+    /// Milliseconds after the first buzz during which buzzes are ordered using
+    /// the timestamps reported by participants (0 to disable).
+    #[arg(short = 's', long, default_value = "0")]
+    pub settling_window: u64,
+    /// Seconds between the WebSocket pings sent to detect dead connections (0 to
+    /// disable).
+    #[arg(short = 'i', long, default_value = "15")]
+    pub heartbeat_interval: u64,
+    /// Seconds to wait for an answer to a ping before considering the
+    /// connection dead.
+    #[arg(short = 't', long, default_value = "10")]
+    pub heartbeat_timeout: u64,
+    /// Use the last address of the X-Forwarded-For header to identify clients.
+    /// Only enable behind a reverse proxy setting it, as clients could
+    /// otherwise spoof their address to evade bans.
+    #[arg(short = 'x', long)]
+    pub trust_proxy: bool,
 }
 
 impl Options {
@@ -27,4 +54,14 @@ impl Options {
             4.. => LevelFilter::Trace,
         }
     }
+
+    pub fn room_config(&self) -> RoomConfig {
+        RoomConfig {
+            host_grace_period: Duration::from_secs(self.host_grace_period),
+            false_start_penalty: Duration::from_secs(self.false_start_penalty),
+            settling_window: Duration::from_millis(self.settling_window),
+            heartbeat_interval: Duration::from_secs(self.heartbeat_interval),
+            heartbeat_timeout: Duration::from_secs(self.heartbeat_timeout),
+        }
+    }
 }
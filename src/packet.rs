@@ -5,21 +5,222 @@ use ulid::Ulid;
 #[derive(Serialize)]
 #[serde(tag = "event", rename_all = "camelCase")]
 pub enum PacketOut {
+    Hello {
+        version: u32,
+        features: Vec<&'static str>,
+        settings: Settings,
+    },
+    #[serde(rename_all = "camelCase")]
+    HostState {
+        participant_count: usize,
+        armed: bool,
+        timer: Option<u64>,
+        buzzed: Vec<Buzz>,
+        selected: Option<Ulid>,
+        latency_compensation: bool,
+    },
+    Welcome {
+        id: Ulid,
+        session: Box<str>,
+        armed: bool,
+        timer: Option<u64>,
+        buzzed: bool,
+        waiting: bool,
+        selected: bool,
+        verdict: Option<Verdict>,
+        team: Option<Box<str>>,
+        round: Ulid,
+    },
     ParticipantCount {
         count: usize,
     },
+    Roster {
+        participants: Vec<RosterEntry>,
+    },
+    ParticipantJoined {
+        id: Ulid,
+        name: Box<str>,
+    },
+    ParticipantLeft {
+        id: Ulid,
+    },
+    ParticipantRenamed {
+        id: Ulid,
+        name: Box<str>,
+    },
+    Latency {
+        id: Ulid,
+        rtt: u64,
+    },
     #[serde(rename_all = "camelCase")]
     Buzzed {
         id: Ulid,
         name: Box<str>,
+        team: Option<Box<str>>,
         timestamp_diff: Option<u64>,
+        in_time: Option<bool>,
+        rtt: Option<u64>,
+    },
+    Judged {
+        id: Ulid,
+        verdict: Verdict,
+    },
+    #[serde(rename_all = "camelCase")]
+    Scoreboard {
+        scores: Vec<Score>,
+        correct_points: i64,
+        incorrect_points: i64,
+    },
+    Teams {
+        teams: Vec<TeamInfo>,
+    },
+    Team {
+        name: Option<Box<str>>,
     },
     Select {
         id: Option<Ulid>,
     },
     Deselect,
+    Correct,
+    Incorrect,
+    Answered,
     Clear,
+    Armed,
+    Locked,
+    RoundStart {
+        id: Ulid,
+    },
+    FalseStart {
+        penalty: u64,
+    },
+    Timer {
+        remaining: u64,
+    },
+    TimerStopped,
+    TimerExpired,
+    Kicked {
+        reason: Option<Box<str>>,
+        banned: bool,
+    },
+    Hosts {
+        id: Ulid,
+        hosts: Vec<HostEntry>,
+    },
+    HostRole {
+        owner: bool,
+        secret: Box<str>,
+    },
+    Promoted {
+        secret: Box<str>,
+    },
+    OwnershipTransferred {
+        name: Option<Box<str>>,
+    },
+    HostDisconnected,
+    HostReconnected,
     HostLeft,
+    RoomClosed {
+        reason: Option<Box<str>>,
+        summary: Summary,
+    },
+    Error {
+        code: ErrorCode,
+        message: Box<str>,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Buzz {
+    pub id: Ulid,
+    pub name: Box<str>,
+    pub team: Option<Box<str>>,
+    pub timestamp_diff: Option<u64>,
+    pub in_time: Option<bool>,
+    pub verdict: Option<Verdict>,
+    pub rtt: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct RosterEntry {
+    pub id: Ulid,
+    pub name: Box<str>,
+    pub rtt: Option<u64>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct HostEntry {
+    pub id: Ulid,
+    pub owner: bool,
+}
+
+#[derive(Serialize)]
+pub struct TeamInfo {
+    pub id: Ulid,
+    pub name: Box<str>,
+    pub members: Vec<Ulid>,
+}
+
+#[derive(Serialize)]
+pub struct Score {
+    pub id: Ulid,
+    pub name: Box<str>,
+    pub score: i64,
+}
+
+/// Configuration of the room, sent during the handshake.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Settings {
+    pub password_protected: bool,
+    pub invite_only: bool,
+    pub public: bool,
+    // In seconds.
+    pub host_grace_period: u64,
+    // In seconds.
+    pub false_start_penalty: u64,
+    // In milliseconds.
+    pub settling_window: u64,
+    // In seconds.
+    pub heartbeat_interval: u64,
+    // In seconds.
+    pub heartbeat_timeout: u64,
+}
+
+/// Final state of a room closed by its owner.
+#[derive(Serialize)]
+pub struct Summary {
+    pub duration: u64,
+    pub scores: Vec<Score>,
+}
+
+#[derive(Serialize, Copy, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum Verdict {
+    Correct,
+    Incorrect,
+}
+
+#[derive(Serialize, Copy, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum ErrorCode {
+    // The message couldn't be parsed as a packet.
+    MalformedPacket,
+    // The packet isn't meant to be sent by this kind of connection.
+    UnexpectedPacket,
+    // The packet is reserved to the owner of the room.
+    Unauthorized,
+    // A value of the packet is out of the accepted range.
+    InvalidValue,
+    // The client didn't start with a hello packet.
+    HandshakeRequired,
+    // The protocol version of the client isn't supported by the server.
+    UnsupportedVersion,
+    // The client lacks a capability required by the room.
+    MissingCapability,
+    // The participant couldn't join the room, such as when resuming a session
+    // whose name was taken in the meantime.
+    JoinRefused,
 }
 
 impl From<PacketOut> for WsMessage {
@@ -31,18 +232,81 @@ impl From<PacketOut> for WsMessage {
 #[derive(Deserialize, Debug)]
 #[serde(tag = "event", rename_all = "camelCase")]
 pub enum PacketIn {
-    Buzz,
+    Hello {
+        version: u32,
+        #[serde(default)]
+        capabilities: Vec<Box<str>>,
+    },
+    // Milliseconds elapsed since the reception of the round start marker.
+    Buzz {
+        round: Option<Ulid>,
+        elapsed: Option<u64>,
+    },
     SelectNext,
     Clear,
+    Arm,
+    Lock,
+    StartTimer {
+        seconds: u64,
+    },
+    StopTimer,
+    Correct,
+    Incorrect,
+    AdjustScore {
+        id: Ulid,
+        delta: i64,
+    },
+    ResetScores,
+    #[serde(rename_all = "camelCase")]
+    ConfigureScoring {
+        correct_points: i64,
+        incorrect_points: i64,
+    },
+    CreateTeam {
+        name: Box<str>,
+    },
+    RenameTeam {
+        id: Ulid,
+        name: Box<str>,
+    },
+    AssignTeam {
+        participant: Ulid,
+        team: Option<Ulid>,
+    },
+    RenameParticipant {
+        id: Ulid,
+        name: Box<str>,
+    },
+    Kick {
+        id: Ulid,
+        reason: Option<Box<str>>,
+    },
+    Ban {
+        id: Ulid,
+        reason: Option<Box<str>>,
+        #[serde(default)]
+        ip: bool,
+    },
+    TransferOwnership {
+        id: Ulid,
+    },
+    SetLatencyCompensation {
+        enabled: bool,
+    },
+    CloseRoom {
+        reason: Option<Box<str>>,
+    },
 }
 
 impl TryFrom<WsMessage> for PacketIn {
-    type Error = ();
+    type Error = Box<str>;
 
     fn try_from(value: WsMessage) -> Result<Self, Self::Error> {
         match value {
-            Message::Text(text) => serde_json::from_str(&text).map_err(|_err| ()),
-            _ => Err(()),
+            Message::Text(text) => {
+                serde_json::from_str(&text).map_err(|err| err.to_string().into())
+            }
+            _ => Err("packets must be sent as text messages".into()),
         }
     }
 }
@@ -6,8 +6,13 @@ document.querySelectorAll('.lobby.panel .mode').forEach((elem) => {
          document.querySelector('.lobby.panel .mode.selected').classList.remove('selected');
 
          elem.classList.add('selected');
-         document.querySelector('.lobby.panel .input.username').style.display = elem.classList.contains('host') ? 'none' : 'block';
-         document.querySelector('.lobby.panel .action').innerText = `${elem.classList.contains('host') ? 'Create' : 'Join'} room`;
+         document.querySelector('.lobby.panel .input.username').style.display = elem.classList.contains('join') ? 'block' : 'none';
+         document.querySelector('.lobby.panel .input.team').style.display = elem.classList.contains('join') ? 'block' : 'none';
+         document.querySelector('.lobby.panel .input.password').style.display = elem.classList.contains('host') ? 'block' : 'none';
+         document.querySelector('.lobby.panel .input.invite-only').style.display = elem.classList.contains('host') ? 'flex' : 'none';
+         document.querySelector('.lobby.panel .input.public').style.display = elem.classList.contains('host') ? 'flex' : 'none';
+         document.querySelector('.lobby.panel').classList.toggle('hosting', elem.classList.contains('host'));
+         document.querySelector('.lobby.panel .action').innerText = `${elem.classList.contains('host') ? 'Create' : elem.classList.contains('spectate') ? 'Display' : 'Join'} room`;
          if (elem.classList.contains('host')) {
              if (document.querySelector('.lobby.panel .input.room > input').value.trim().length === 0) {
                  document.querySelector('.lobby.panel .input.room > input').value = `Room ${1000 + Math.floor(Math.random() * 9000)}`;
@@ -31,6 +36,7 @@ document.querySelector('.lobby.panel .input.username > input').addEventListener(
 });
 
 const roomPlaceholder = new URLSearchParams(window.location.search).get('room')?.trim();
+const invite = new URLSearchParams(window.location.search).get('invite');
 if (roomPlaceholder) {
     document.querySelector('.lobby.panel .input.room > input').value = roomPlaceholder;
     document.querySelector('.lobby.panel .input.username > input').focus();
@@ -40,6 +46,32 @@ if (roomPlaceholder) {
     document.querySelector('.lobby.panel .input.room > input').select();
 }
 
+// Keep the directory of public rooms up to date while in the lobby.
+new EventSource('/rooms/live').addEventListener('message', (event) => {
+    const rooms = JSON.parse(event.data);
+    document.querySelector('.lobby.panel .public-rooms').replaceChildren(...rooms.map((room) => {
+        const roomElem = document.createElement('div');
+        roomElem.classList.add('public-room');
+
+        const nameElem = document.createElement('div');
+        nameElem.classList.add('name');
+        nameElem.innerText = `${room.passwordProtected ? '🔒 ' : ''}${room.name}`;
+        roomElem.append(nameElem);
+
+        const detailsElem = document.createElement('div');
+        detailsElem.classList.add('details');
+        const age = room.age < 3600 ? `${Math.floor(room.age / 60)}m` : `${Math.floor(room.age / 3600)}h`;
+        detailsElem.innerText = `${room.participantCount} · ${age}`;
+        roomElem.append(detailsElem);
+
+        roomElem.addEventListener('click', () => {
+            document.querySelector('.lobby.panel .input.room > input').value = room.name;
+            document.querySelector('.lobby.panel .input.username > input').focus();
+        });
+        return roomElem;
+    }));
+});
+
 function proceed(event) {
     event.preventDefault();
 
@@ -52,6 +84,9 @@ function proceed(event) {
                 },
                 body: JSON.stringify({
                     name: document.querySelector('.lobby.panel .room.input > input').value.trim(),
+                    password: document.querySelector('.lobby.panel .password.input > input').value,
+                    inviteOnly: document.querySelector('.lobby.panel .invite-only.input > input').checked,
+                    public: document.querySelector('.lobby.panel .public.input > input').checked,
                 }),
             });
             let data = await response.json();
@@ -59,21 +94,77 @@ function proceed(event) {
                 alert(`${data.error}.`);
                 return;
             }
-            let { id, name } = data;
+            let { id, name, secret, code } = data;
 
-            run('host', name, new WebSocket(`${location.origin.replace(/^http/, 'ws')}/rooms/${id}/host`), document.querySelector('.host.panel'));
+            run('host', { id, name, secret, code }, `${location.origin.replace(/^http/, 'ws')}/rooms/${id}/host?secret=${secret}`, document.querySelector('.host.panel'));
         } else {
-            const response = await fetch(`/rooms/id?name=${document.querySelector('.lobby.panel .room.input > input').value.trim()}`, {
-                method: 'GET',
+            const spectate = document.querySelector('.lobby.panel .mode.selected').classList.contains('spectate');
+            const room = document.querySelector('.lobby.panel .room.input > input').value.trim();
+            // Rooms can be joined using their name or their join code.
+            let byCode = /^\d{3}[\s-]?\d{3}$/.test(room);
+            const params = new URLSearchParams({
+                [byCode ? 'code' : 'name']: room,
             });
-            let data = await response.json();
+            // Spectators don't need a username.
+            if (!spectate) {
+                params.set('username', document.querySelector('.lobby.panel .username.input > input').value.trim());
+            }
+            if (invite !== null) {
+                params.set('invite', invite);
+            }
+            let response, data;
+            while (true) {
+                response = await fetch(`/rooms/${byCode ? 'code' : 'id'}?${params}`, {
+                    method: 'GET',
+                });
+                data = await response.json();
+                // Room names may look like join codes.
+                if (response.status === 404 && byCode) {
+                    byCode = false;
+                    params.delete('code');
+                    params.set('name', room);
+                    continue;
+                }
+                // Ask for the password until it's correct or the user gives up.
+                if (response.status === 401 || (response.status === 403 && params.has('password'))) {
+                    const password = prompt(response.status === 401 ? 'This room requires a password:' : 'Invalid password, try again:');
+                    if (password === null) {
+                        return;
+                    }
+                    params.set('password', password);
+                    continue;
+                }
+                // Offer the suggested alternative if the username is already used.
+                if (response.status === 409 && data.suggestion) {
+                    const username = prompt('This username is already taken, pick another one:', data.suggestion)?.trim();
+                    if (!username) {
+                        return;
+                    }
+                    document.querySelector('.lobby.panel .username.input > input').value = username;
+                    params.set('username', username);
+                    continue;
+                }
+                break;
+            }
             if (data.error) {
                 alert(`${data.error}.`);
                 return;
             }
             let { id, name } = data;
 
-            run('participate', name, new WebSocket(`${location.origin.replace(/^http/, 'ws')}/rooms/${id}/participate?name=${document.querySelector('.lobby.panel .username.input > input').value.trim()}`), document.querySelector('.participate.panel'));
+            params.delete('code');
+            if (spectate) {
+                params.delete('name');
+                run('spectate', { id, name }, `${location.origin.replace(/^http/, 'ws')}/rooms/${id}/spectate?${params}`, document.querySelector('.spectate.panel'));
+                return;
+            }
+            params.set('name', params.get('username'));
+            params.delete('username');
+            const team = document.querySelector('.lobby.panel .team.input > input').value.trim();
+            if (team.length > 0) {
+                params.set('team', team);
+            }
+            run('participate', { id, name }, `${location.origin.replace(/^http/, 'ws')}/rooms/${id}/participate?${params}`, document.querySelector('.participate.panel'));
         }
     })();
 }
@@ -81,84 +172,372 @@ function proceed(event) {
 document.querySelector('.lobby.panel .action').addEventListener('click', proceed);
 document.querySelector('.lobby.panel form').addEventListener('submit', proceed);
 
-function run(mode, name, socket, panelElem) {
+const RECONNECT_DELAY = 2000;
+const MAX_RECONNECT_ATTEMPTS = 10;
+// Version of the packets understood by this client, checked by the server.
+const PROTOCOL_VERSION = 1;
+const CAPABILITIES = ['reportedTime', 'heartbeat'];
+
+function run(mode, room, socketUrl, panelElem) {
     document.body.classList.replace('lobby', mode);
-    panelElem.querySelector('.title.panel > .labels > .label').innerText = name;
+    panelElem.querySelector('.title.panel > .labels > .label').innerText = room.name;
+    if (room.code !== undefined) {
+        panelElem.querySelector('.title.panel > .labels > .code').innerText = `Join code ${room.code.slice(0, 3)} ${room.code.slice(3)}`;
+    }
+
+    // Only the owner can bring co-hosts in.
+    panelElem.querySelector('.cohost.action')?.style.setProperty('display', room.cohost ? 'none' : '');
 
     const url = new URL(window.location);
-    url.searchParams.set('room', name);
+    url.searchParams.set('room', room.name);
     window.history.replaceState(null, '', url.toString());
 
+    let socket = null;
     let buzzs = [];
     let buzzed = false;
     let initiatedLeave = false;
+    let participantCount = 0;
+    let armed = true;
+    let timerRunning = false;
+    let lastTimerDuration = 30;
+    let scoring = { correctPoints: 1, incorrectPoints: 0 };
+    let latencyCompensation = false;
+    let features = [];
+    // Last round start marker, buzzes are timestamped relatively to its reception.
+    let roundStart = null;
+    // Connected participants, only maintained for the host.
+    let roster = new Map();
+    // Round trip times of the participants, measured by the server heartbeat.
+    let latencies = new Map();
+    // Query parameters used to resume the session after a disconnection.
+    let resume = null;
+    let reconnectAttempts = 0;
 
-    socket.addEventListener('message', (message) => {
-        const data = JSON.parse(message.data);
-        switch (data.event) {
-            case 'participantCount':
-                panelElem.querySelector('.title.panel > .labels > .sub-label').innerText = `${data.count} participant${data.count !== 1 ? 's' : ''}`;
+    function connect(url) {
+        socket = new WebSocket(url);
+        socket.addEventListener('open', () => {
+            reconnectAttempts = 0;
+            socket.send(JSON.stringify({ event: 'hello', version: PROTOCOL_VERSION, capabilities: CAPABILITIES }));
+        });
+        socket.addEventListener('message', handleMessage);
+        socket.addEventListener('close', handleClose);
+    }
+
+    function renderParticipantCount() {
+        panelElem.querySelector('.title.panel > .labels > .sub-label').innerText = `${participantCount} participant${participantCount !== 1 ? 's' : ''}`;
+    }
+
+    function addBuzz(id, name, team, timestampDiff, inTime, verdict, rtt) {
+        const buzzElem = document.createElement('div');
+        buzzElem.classList.add('buzz');
+        if (buzzs.length === 0) {
+            buzzElem.classList.add('selected');
+        }
+        if (inTime === false) {
+            buzzElem.classList.add('late');
+            buzzElem.title = 'Buzzed after the countdown';
+        }
+
+        const usernameElem = document.createElement('div');
+        usernameElem.classList.add('username', 'selectable');
+        usernameElem.innerText = team !== null ? `${name} (${team})` : name;
+        usernameElem.title = usernameElem.innerText;
+        buzzElem.append(usernameElem);
+
+        const rightElem = document.createElement('div');
+        rightElem.classList.add('right');
+        buzzElem.append(rightElem);
+
+        const positionElem = document.createElement('div');
+        positionElem.classList.add('position');
+        switch (buzzs.length + 1) {
+            case 1:
+                positionElem.innerText = '1st';
                 break;
-            case 'buzzed':
-                const { id, name, timestampDiff } = data;
+            case 2:
+                positionElem.innerText = '2nd';
+                break;
+            case 3:
+                positionElem.innerText = '3rd';
+                break;
+            default:
+                positionElem.innerText = `${buzzs.length + 1}th`;
+                break;
+        }
+        rightElem.append(positionElem);
 
-                const buzzElem = document.createElement('div');
-                buzzElem.classList.add('buzz');
-                if (buzzs.length === 0) {
-                    buzzElem.classList.add('selected');
-                }
+        if (timestampDiff !== null) {
+            const timestampElem = document.createElement('div');
+            timestampElem.classList.add('timestamp');
+            if (timestampDiff < 1000) {
+                timestampElem.innerText = `+${timestampDiff}ms`;
+            } else if (timestampDiff < 10000) {
+                timestampElem.innerText = `+${Math.floor(timestampDiff / 100) / 10}s`;
+            } else {
+                timestampElem.innerText = `+${Math.floor(timestampDiff / 1000)}s`;
+            }
+            rightElem.append(timestampElem);
+        }
 
-                const usernameElem = document.createElement('div');
-                usernameElem.classList.add('username', 'selectable');
-                usernameElem.innerText = name;
-                usernameElem.title = name;
-                buzzElem.append(usernameElem);
-
-                const rightElem = document.createElement('div');
-                rightElem.classList.add('right');
-                buzzElem.append(rightElem);
-
-                const positionElem = document.createElement('div');
-                positionElem.classList.add('position');
-                switch (buzzs.length + 1) {
-                    case 1:
-                        positionElem.innerText = '1st';
-                        break;
-                    case 2:
-                        positionElem.innerText = '2nd';
-                        break;
-                    case 3:
-                        positionElem.innerText = '3rd';
-                        break;
-                    default:
-                        positionElem.innerText = `${buzzs.length + 1}th`;
-                        break;
+        if (rtt !== null) {
+            const rttElem = document.createElement('div');
+            rttElem.classList.add('rtt');
+            rttElem.innerText = `~${rtt}ms RTT`;
+            rttElem.title = 'Estimated round trip time';
+            rightElem.append(rttElem);
+        }
+
+        if (mode === 'host') {
+            const verdictsElem = document.createElement('div');
+            verdictsElem.classList.add('verdicts');
+            [['correct', '✓', '(C)'], ['incorrect', '✗', '(X)']].forEach(([event, label, title]) => {
+                const verdictElem = document.createElement('div');
+                verdictElem.classList.add('verdict', event);
+                verdictElem.innerText = label;
+                verdictElem.title = title;
+                verdictElem.addEventListener('click', () => socket.send(JSON.stringify({ event })));
+                verdictsElem.append(verdictElem);
+            });
+            buzzElem.insertBefore(verdictsElem, rightElem);
+        }
+
+        panelElem.querySelector('.inner.panel').append(buzzElem);
+        buzzs.push({ id, elem: buzzElem });
+        if (verdict !== null) {
+            judgeBuzz(id, verdict);
+        }
+    }
+
+    function judgeBuzz(id, verdict) {
+        buzzs.find((b) => b.id === id)?.elem.classList.add(verdict);
+    }
+
+    function renderRoster() {
+        panelElem.querySelector('.title.panel > .labels > .roster').replaceChildren(...[...roster].map(([id, name]) => {
+            const participantElem = document.createElement('div');
+            participantElem.classList.add('participant');
+
+            const nameElem = document.createElement('div');
+            nameElem.classList.add('name');
+            nameElem.innerText = name;
+            participantElem.append(nameElem);
+
+            if (mode === 'host') {
+                if (latencies.has(id)) {
+                    const latencyElem = document.createElement('div');
+                    latencyElem.classList.add('latency');
+                    latencyElem.innerText = `${latencies.get(id)}ms`;
+                    latencyElem.title = 'Round trip time';
+                    participantElem.append(latencyElem);
                 }
-                rightElem.append(positionElem);
-
-                if (timestampDiff !== null) {
-                    const timestampElem = document.createElement('div');
-                    timestampElem.classList.add('timestamp');
-                    if (timestampDiff < 1000) {
-                        timestampElem.innerText = `+${timestampDiff}ms`;
-                    } else if (timestampDiff < 10000) {
-                        timestampElem.innerText = `+${Math.floor(timestampDiff / 100) / 10}s`;
-                    } else {
-                        timestampElem.innerText = `+${Math.floor(timestampDiff / 1000)}s`;
-                    }
-                    rightElem.append(timestampElem);
+
+                nameElem.title = 'Rename';
+                nameElem.addEventListener('click', () => handleRenameParticipant(id, name));
+
+                const kickElem = document.createElement('div');
+                kickElem.classList.add('kick');
+                kickElem.innerText = '✗';
+                kickElem.title = 'Kick or ban';
+                kickElem.addEventListener('click', () => handleKick(id, name));
+                participantElem.append(kickElem);
+
+                if (!room.cohost) {
+                    const promoteElem = document.createElement('div');
+                    promoteElem.classList.add('promote');
+                    promoteElem.innerText = '★';
+                    promoteElem.title = 'Hand the room over';
+                    promoteElem.addEventListener('click', () => handleTransferOwnership(id, name));
+                    participantElem.append(promoteElem);
                 }
+            }
+            return participantElem;
+        }));
+    }
+
+    function renderHosts(self, hosts) {
+        const hostsElem = panelElem.querySelector('.title.panel > .labels > .hosts');
+        if (hosts.length < 2) {
+            hostsElem.replaceChildren();
+            return;
+        }
+        hostsElem.replaceChildren(...hosts.map(({ id, owner }) => {
+            const hostElem = document.createElement('div');
+            hostElem.classList.add('host');
+            hostElem.innerText = `${owner ? 'Owner' : 'Co-host'}${id === self ? ' (you)' : ''}`;
+            if (!room.cohost && !owner) {
+                hostElem.classList.add('promotable');
+                hostElem.title = 'Hand the room over';
+                hostElem.addEventListener('click', () => handleTransferOwnership(id, 'this co-host'));
+            }
+            return hostElem;
+        }));
+    }
+
+    function renderTeams(teams) {
+        const teamsElem = panelElem.querySelector('.title.panel > .labels > .teams');
+        teamsElem.replaceChildren(...teams.map(({ id, name, members }) => {
+            const teamElem = document.createElement('div');
+            teamElem.classList.add('team');
+
+            const nameElem = document.createElement('div');
+            nameElem.classList.add('name');
+            nameElem.innerText = `${name} (${members.length})`;
+            nameElem.title = [name, ...members.map((member) => roster.get(member) ?? '?')].join('\n');
+            teamElem.append(nameElem);
+
+            if (mode === 'host') {
+                nameElem.addEventListener('click', () => handleRenameTeam(id, name));
+
+                const assignElem = document.createElement('div');
+                assignElem.classList.add('assign');
+                assignElem.innerText = '+';
+                assignElem.title = 'Move a participant to this team';
+                assignElem.addEventListener('click', () => handleAssignTeam(id, name));
+                teamElem.append(assignElem);
+            }
+            return teamElem;
+        }));
+
+        if (mode === 'host') {
+            const createElem = document.createElement('div');
+            createElem.classList.add('team', 'create');
+            createElem.innerText = '+ Team';
+            createElem.addEventListener('click', handleCreateTeam);
+            teamsElem.append(createElem);
+        }
+    }
+
+    function renderScoreboard(scores) {
+        const scoreboardElem = panelElem.querySelector('.title.panel > .labels > .scoreboard');
+        scoreboardElem.replaceChildren(...scores.map(({ id, name, score }) => {
+            const scoreElem = document.createElement('div');
+            scoreElem.classList.add('score');
+            scoreElem.innerText = `${name} ${score}`;
+            if (mode === 'host') {
+                scoreElem.title = 'Adjust score';
+                scoreElem.addEventListener('click', () => handleAdjustScore(id, name));
+            }
+            return scoreElem;
+        }));
+        if (mode === 'host') {
+            const settingsElem = document.createElement('div');
+            settingsElem.classList.add('score', 'settings');
+            settingsElem.innerText = `+${scoring.correctPoints} / ${scoring.incorrectPoints}`;
+            settingsElem.title = 'Scoring settings';
+            settingsElem.addEventListener('click', handleScoring);
+            scoreboardElem.append(settingsElem);
+        }
+    }
+
+    function renderArmed() {
+        panelElem.querySelector('.arm.action')?.replaceChildren(armed ? 'Lock' : 'Arm');
+        panelElem.querySelector('.buzzer')?.classList.toggle('locked', !armed);
+    }
+
+    function renderLatencyCompensation() {
+        const compensationElem = panelElem.querySelector('.title.panel > .labels > .compensation');
+        if (compensationElem !== null) {
+            compensationElem.innerText = `Latency compensation ${latencyCompensation ? 'on' : 'off'}`;
+            compensationElem.hidden = !features.includes('latencyCompensation');
+        }
+    }
+
+    function renderTimer(text) {
+        panelElem.querySelector('.title.panel > .labels > .countdown').innerText = text;
+    }
+
+    function renderTeam(name) {
+        panelElem.querySelector('.title.panel > .labels > .team').innerText = name !== null ? `Team ${name}` : '';
+    }
+
+    function selectBuzz(id) {
+        panelElem.querySelector('.inner.panel .buzz.selected')?.classList.remove('selected');
+        const buzzElem = buzzs.find((b) => b.id === id).elem;
+        buzzElem.classList.add('selected');
+        buzzElem.scrollIntoView({ behavior: 'smooth', block: 'nearest' });
+    }
 
-                panelElem.querySelector('.inner.panel').append(buzzElem);
-                buzzs.push({ id, elem: buzzElem });
+    function handleMessage(message) {
+        const data = JSON.parse(message.data);
+        switch (data.event) {
+            case 'hostState':
+                resume = { secret: room.secret };
+                // The room only exists once the host is connected.
+                panelElem.querySelector('.title.panel > .labels > .qr').href = `/rooms/${room.id}/qr.svg`;
+                panelElem.querySelector('.title.panel > .labels > .qr > img').src = `/rooms/${room.id}/qr.svg`;
+                participantCount = data.participantCount;
+                renderParticipantCount();
+                armed = data.armed;
+                renderArmed();
+                timerRunning = data.timer !== null;
+                renderTimer(timerRunning ? `${data.timer}s` : '');
+                buzzs = [];
+                panelElem.querySelector('.inner.panel').replaceChildren();
+                data.buzzed.forEach((buzz) => addBuzz(buzz.id, buzz.name, buzz.team, buzz.timestampDiff, buzz.inTime, buzz.verdict, buzz.rtt));
+                latencyCompensation = data.latencyCompensation;
+                renderLatencyCompensation();
+                if (data.selected !== null) {
+                    selectBuzz(data.selected);
+                }
+                break;
+            case 'welcome':
+                resume = { session: data.session };
+                roundStart = { id: data.round, receivedAt: performance.now() };
+                armed = data.armed;
+                renderArmed();
+                timerRunning = data.timer !== null;
+                renderTimer(timerRunning ? `${data.timer}s` : '');
+                buzzed = data.buzzed;
+                panelElem.querySelector('.inner.panel').classList.toggle('selected', data.selected);
+                panelElem.querySelector('.inner.panel').classList.toggle('waiting', data.waiting);
+                panelElem.querySelector('.inner.panel').classList.toggle('correct', data.verdict === 'correct');
+                panelElem.querySelector('.inner.panel').classList.toggle('incorrect', data.verdict === 'incorrect');
+                renderTeam(data.team);
+                break;
+            case 'participantCount':
+                participantCount = data.count;
+                renderParticipantCount();
+                break;
+            case 'roster':
+                roster = new Map(data.participants.map(({ id, name }) => [id, name]));
+                latencies = new Map(data.participants.filter(({ rtt }) => rtt !== null).map(({ id, rtt }) => [id, rtt]));
+                renderRoster();
+                break;
+            case 'participantJoined':
+            case 'participantRenamed':
+                roster.set(data.id, data.name);
+                renderRoster();
+                break;
+            case 'participantLeft':
+                roster.delete(data.id);
+                latencies.delete(data.id);
+                renderRoster();
+                break;
+            case 'latency':
+                latencies.set(data.id, data.rtt);
+                renderRoster();
+                break;
+            case 'buzzed':
+                addBuzz(data.id, data.name, data.team, data.timestampDiff, data.inTime, null, data.rtt);
+                break;
+            case 'teams':
+                renderTeams(data.teams);
+                break;
+            case 'team':
+                renderTeam(data.name);
+                break;
+            case 'judged':
+                judgeBuzz(data.id, data.verdict);
+                break;
+            case 'scoreboard':
+                scoring = { correctPoints: data.correctPoints, incorrectPoints: data.incorrectPoints };
+                renderScoreboard(data.scores);
                 break;
             case 'select':
                 switch (mode) {
                     case 'host':
-                        panelElem.querySelector('.inner.panel .buzz.selected').classList.remove('selected');
-                        const buzzElem = buzzs.find((b) => b.id === data.id).elem;
-                        buzzElem.classList.add('selected');
-                        buzzElem.scrollIntoView({ behavior: 'smooth', block: 'nearest' });
+                    case 'spectate':
+                        selectBuzz(data.id);
                         break;
                     case 'participate':
                         panelElem.querySelector('.inner.panel').classList.remove('waiting');
@@ -169,43 +548,303 @@ function run(mode, name, socket, panelElem) {
             case 'deselect':
                 panelElem.querySelector('.inner.panel').classList.remove('selected');
                 break;
+            case 'correct':
+            case 'incorrect':
+                panelElem.querySelector('.inner.panel').classList.remove('selected');
+                panelElem.querySelector('.inner.panel').classList.add(data.event);
+                break;
+            case 'answered':
+                panelElem.querySelector('.inner.panel').classList.remove('waiting');
+                break;
             case 'clear':
-                panelElem.querySelector('.inner.panel').classList.remove('selected', 'waiting');
+                if (mode === 'host' || mode === 'spectate') {
+                    buzzs = [];
+                    panelElem.querySelector('.inner.panel').replaceChildren();
+                }
+                panelElem.querySelector('.inner.panel').classList.remove('selected', 'waiting', 'correct', 'incorrect');
                 buzzed = false;
+                timerRunning = false;
+                renderTimer('');
+                break;
+            case 'armed':
+            case 'locked':
+                armed = data.event === 'armed';
+                renderArmed();
+                break;
+            case 'timer':
+                timerRunning = true;
+                renderTimer(`${data.remaining}s`);
+                break;
+            case 'timerStopped':
+                timerRunning = false;
+                renderTimer('');
+                break;
+            case 'timerExpired':
+                timerRunning = false;
+                renderTimer('Time\'s up');
+                break;
+            case 'roundStart':
+                roundStart = { id: data.id, receivedAt: performance.now() };
+                break;
+            case 'falseStart':
+                panelElem.querySelector('.inner.panel').classList.add('false-start');
+                setTimeout(() => panelElem.querySelector('.inner.panel').classList.remove('false-start'), data.penalty);
+                break;
+            case 'kicked':
+                exit();
+                alert(`You have been ${data.banned ? 'banned' : 'kicked'} from the room${data.reason !== null ? `: ${data.reason}` : ''}.`);
+                break;
+            case 'hosts':
+                renderHosts(data.id, data.hosts);
+                break;
+            case 'hostRole':
+                room.secret = data.secret;
+                room.cohost = !data.owner;
+                resume = { secret: room.secret };
+                panelElem.querySelector('.cohost.action').style.setProperty('display', room.cohost ? 'none' : '');
+                renderRoster();
+                break;
+            case 'promoted':
+                exit();
+                run('host', { id: room.id, name: room.name, secret: data.secret }, `${location.origin.replace(/^http/, 'ws')}/rooms/${room.id}/host?secret=${encodeURIComponent(data.secret)}`, document.querySelector('.host.panel'));
+                break;
+            case 'ownershipTransferred':
+                panelElem.querySelector('.title.panel > .labels > .sub-label').innerText = `${data.name ?? 'A co-host'} is now hosting the room`;
+                setTimeout(renderParticipantCount, 3000);
+                break;
+            case 'hostDisconnected':
+                panelElem.querySelector('.title.panel > .labels > .sub-label').innerText = 'Waiting for the host to reconnect';
+                break;
+            case 'hostReconnected':
+                renderParticipantCount();
                 break;
             case 'hostLeft':
                 exit();
-                alert('The host has closed the room.');
+                alert('The host has left, the room is closed.');
+                break;
+            case 'roomClosed': {
+                exit();
+                const scores = data.summary.scores.map(({ name, score }, i) => `${i + 1}. ${name} (${score})`);
+                alert([
+                    `The host has closed the room${data.reason !== null ? `: ${data.reason}` : ''}.`,
+                    `Duration: ${Math.floor(data.summary.duration / 60)} minutes.`,
+                    ...(scores.length > 0 ? ['', 'Final scores:', ...scores] : []),
+                ].join('\n'));
+                break;
+            }
+            case 'hello':
+                console.info(`Connected to a server using protocol version ${data.version}, with ${data.features.join(', ')}.`);
+                features = data.features;
+                renderLatencyCompensation();
+                break;
+            case 'error':
+                if (data.code === 'handshakeRequired' || data.code === 'unsupportedVersion' || data.code === 'missingCapability') {
+                    exit();
+                    alert(`Incompatible server: ${data.message}.`);
+                } else if (data.code === 'joinRefused') {
+                    exit();
+                    alert(`Couldn't join the room: ${data.message}.`);
+                } else if (data.code === 'unauthorized' || data.code === 'invalidValue') {
+                    alert(`Action refused: ${data.message}.`);
+                } else {
+                    console.warn(`Packet rejected (${data.code}): ${data.message}`);
+                }
                 break;
         }
-    });
+    }
 
-    socket.addEventListener('error', () => {
-        if (!initiatedLeave) {
-            alert('An error occurred');
+    function handleClose() {
+        if (initiatedLeave) {
+            return;
+        }
+        if (resume !== null && reconnectAttempts < MAX_RECONNECT_ATTEMPTS) {
+            reconnectAttempts += 1;
+            const resumeUrl = new URL(socketUrl);
+            Object.entries(resume).forEach(([key, value]) => resumeUrl.searchParams.set(key, value));
+            setTimeout(() => connect(resumeUrl.toString()), RECONNECT_DELAY);
+            return;
         }
         exit();
-    });
+        alert('An error occurred');
+    }
 
     function handleBuzz() {
-        socket.send(JSON.stringify({ event: 'buzz' }));
-        if (!buzzed) {
+        socket.send(JSON.stringify({
+            event: 'buzz',
+            round: roundStart?.id ?? null,
+            elapsed: roundStart !== null ? Math.round(performance.now() - roundStart.receivedAt) : null,
+        }));
+        if (armed && !buzzed) {
             buzzed = true;
             panelElem.querySelector('.inner.panel').classList.add('waiting');
         }
     }
 
     function handleLeave() {
-        initiatedLeave = true;
-        if (mode === 'participate' || confirm('You\'re about to close the room and kick every participant out. Confirm?')) {
+        if (mode !== 'host' || room.cohost) {
             exit();
+            return;
+        }
+        const reason = prompt('You\'re about to close the room and kick every participant out. Reason (optional):');
+        if (reason === null) {
+            return;
         }
+        socket.send(JSON.stringify({ event: 'closeRoom', reason: reason.trim() || null }));
+        exit();
     }
 
     function handleClear() {
         socket.send(JSON.stringify({ event: 'clear' }));
-        buzzs = [];
-        panelElem.querySelector('.inner.panel').replaceChildren();
+    }
+
+    function handleInvite() {
+        (async function() {
+            const response = await fetch(`/rooms/${room.id}/invites`, {
+                method: 'POST',
+                headers: {
+                    'Authorization': `Bearer ${room.secret}`,
+                },
+            });
+            const data = await response.json();
+            if (data.error) {
+                alert(`${data.error}.`);
+                return;
+            }
+
+            const link = new URL(location.origin);
+            link.searchParams.set('room', room.name);
+            link.searchParams.set('invite', data.invite);
+            prompt('Share this invite link:', link.toString());
+        })();
+    }
+
+    function handleCohost() {
+        (async function() {
+            const response = await fetch(`/rooms/${room.id}/cohosts`, {
+                method: 'POST',
+                headers: {
+                    'Authorization': `Bearer ${room.secret}`,
+                },
+            });
+            const data = await response.json();
+            if (data.error) {
+                alert(`${data.error}.`);
+                return;
+            }
+
+            const link = new URL(location.origin);
+            link.searchParams.set('room', room.name);
+            link.searchParams.set('host', room.id);
+            link.searchParams.set('secret', data.secret);
+            prompt('Share this co-host link:', link.toString());
+        })();
+    }
+
+    function handleArm() {
+        socket.send(JSON.stringify({ event: armed ? 'lock' : 'arm' }));
+    }
+
+    function handleTimer() {
+        if (timerRunning) {
+            socket.send(JSON.stringify({ event: 'stopTimer' }));
+            return;
+        }
+        const seconds = parseInt(prompt('Countdown duration (seconds):', lastTimerDuration), 10);
+        if (!(seconds > 0)) {
+            return;
+        }
+        lastTimerDuration = seconds;
+        socket.send(JSON.stringify({ event: 'startTimer', seconds }));
+    }
+
+    function handleAdjustScore(id, name) {
+        const delta = parseInt(prompt(`Points to add to ${name} (negative to remove):`), 10);
+        if (Number.isNaN(delta) || delta === 0) {
+            return;
+        }
+        socket.send(JSON.stringify({ event: 'adjustScore', id, delta }));
+    }
+
+    function handleScoring() {
+        const input = prompt('Points for a correct and an incorrect answer (e.g. "1, -1"), or "reset" to reset every score:', `${scoring.correctPoints}, ${scoring.incorrectPoints}`);
+        if (input === null) {
+            return;
+        }
+        if (input.trim().toLowerCase() === 'reset') {
+            socket.send(JSON.stringify({ event: 'resetScores' }));
+            return;
+        }
+        const [correctPoints, incorrectPoints] = input.split(',').map((points) => parseInt(points, 10));
+        if (Number.isNaN(correctPoints) || Number.isNaN(incorrectPoints)) {
+            return;
+        }
+        socket.send(JSON.stringify({ event: 'configureScoring', correctPoints, incorrectPoints }));
+    }
+
+    function handleRenameParticipant(id, currentName) {
+        const name = prompt(`New name for ${currentName}:`, currentName)?.trim();
+        if (!name || name === currentName) {
+            return;
+        }
+        socket.send(JSON.stringify({ event: 'renameParticipant', id, name }));
+    }
+
+    function handleKick(id, name) {
+        const reason = prompt(`Reason for kicking ${name} (optional):`);
+        if (reason === null) {
+            return;
+        }
+        const packet = { id, reason: reason.trim() || null };
+        if (confirm(`Also ban ${name} from the room?`)) {
+            packet.event = 'ban';
+            packet.ip = confirm(`Ban every participant sharing ${name}'s IP address?`);
+        } else {
+            packet.event = 'kick';
+        }
+        socket.send(JSON.stringify(packet));
+    }
+
+    function handleTransferOwnership(id, name) {
+        if (!confirm(`Hand the room over to ${name}? You will stay as a co-host.`)) {
+            return;
+        }
+        socket.send(JSON.stringify({ event: 'transferOwnership', id }));
+    }
+
+    function handleCreateTeam() {
+        const name = prompt('Team name:')?.trim();
+        if (!name) {
+            return;
+        }
+        socket.send(JSON.stringify({ event: 'createTeam', name }));
+    }
+
+    function handleRenameTeam(id, currentName) {
+        const name = prompt(`New name for team ${currentName}:`, currentName)?.trim();
+        if (!name || name === currentName) {
+            return;
+        }
+        socket.send(JSON.stringify({ event: 'renameTeam', id, name }));
+    }
+
+    function handleAssignTeam(team, teamName) {
+        const name = prompt(`Participant to move to team ${teamName}:`)?.trim().toLowerCase();
+        if (!name) {
+            return;
+        }
+        const participant = [...roster].find(([, n]) => n.toLowerCase() === name);
+        if (participant === undefined) {
+            alert('Unknown participant.');
+            return;
+        }
+        socket.send(JSON.stringify({ event: 'assignTeam', participant: participant[0], team }));
+    }
+
+    function handleLatencyCompensation() {
+        if (!features.includes('latencyCompensation')) {
+            return;
+        }
+        socket.send(JSON.stringify({ event: 'setLatencyCompensation', enabled: !latencyCompensation }));
     }
 
     function handleSelect() {
@@ -224,6 +863,26 @@ function run(mode, name, socket, panelElem) {
                     case 'ArrowDown':
                         handleSelect();
                         break;
+                    case 'Enter':
+                        handleArm();
+                        break;
+                    case 't':
+                        handleTimer();
+                        break;
+                    case 'l':
+                        handleLatencyCompensation();
+                        break;
+                    case 'c':
+                        socket.send(JSON.stringify({ event: 'correct' }));
+                        break;
+                    case 'x':
+                        socket.send(JSON.stringify({ event: 'incorrect' }));
+                        break;
+                }
+                break;
+            case 'spectate':
+                if (event.key === 'Escape') {
+                    handleLeave();
                 }
                 break;
             case 'participate':
@@ -246,26 +905,57 @@ function run(mode, name, socket, panelElem) {
     }
 
     function exit() {
+        initiatedLeave = true;
         socket.close();
         panelElem.querySelectorAll('.inner.panel .buzz').forEach((elem) => elem.remove());
-        panelElem.querySelector('.inner.panel').classList.remove('selected', 'waiting');
+        panelElem.querySelector('.inner.panel').classList.remove('selected', 'waiting', 'correct', 'incorrect', 'false-start');
+        panelElem.querySelector('.buzzer')?.classList.remove('locked');
+        renderTimer('');
+        panelElem.querySelector('.title.panel > .labels > .scoreboard').replaceChildren();
+        panelElem.querySelector('.title.panel > .labels > .qr > img')?.removeAttribute('src');
+        panelElem.querySelector('.title.panel > .labels > .code')?.replaceChildren();
+        panelElem.querySelector('.title.panel > .labels > .teams')?.replaceChildren();
+        panelElem.querySelector('.title.panel > .labels > .roster')?.replaceChildren();
+        panelElem.querySelector('.title.panel > .labels > .hosts')?.replaceChildren();
+        panelElem.querySelector('.title.panel > .labels > .team')?.replaceChildren();
         panelElem.querySelector('.buzzer')?.removeEventListener('click', handleBuzz);
         panelElem.querySelector('.leave.action')?.removeEventListener('click', handleLeave);
+        panelElem.querySelector('.invite.action')?.removeEventListener('click', handleInvite);
+        panelElem.querySelector('.cohost.action')?.removeEventListener('click', handleCohost);
+        panelElem.querySelector('.arm.action')?.removeEventListener('click', handleArm);
+        panelElem.querySelector('.timer.action')?.removeEventListener('click', handleTimer);
         panelElem.querySelector('.clear.action')?.removeEventListener('click', handleClear);
         panelElem.querySelector('.select.action')?.removeEventListener('click', handleSelect);
+        panelElem.querySelector('.title.panel > .labels > .compensation')?.removeEventListener('click', handleLatencyCompensation);
         window.removeEventListener('keydown', keyDown);
         window.removeEventListener('keyup', keyUp);
 
         const url = new URL(window.location);
         url.searchParams.delete('room');
+        url.searchParams.delete('invite');
+        url.searchParams.delete('host');
+        url.searchParams.delete('secret');
         window.history.replaceState(null, '', url.toString());
         document.body.classList.replace(mode, 'lobby');
     }
 
+    connect(socketUrl);
     panelElem.querySelector('.buzzer')?.addEventListener('click', handleBuzz);
     panelElem.querySelector('.leave.action')?.addEventListener('click', handleLeave);
+    panelElem.querySelector('.invite.action')?.addEventListener('click', handleInvite);
+    panelElem.querySelector('.cohost.action')?.addEventListener('click', handleCohost);
+    panelElem.querySelector('.arm.action')?.addEventListener('click', handleArm);
+    panelElem.querySelector('.timer.action')?.addEventListener('click', handleTimer);
     panelElem.querySelector('.clear.action')?.addEventListener('click', handleClear);
     panelElem.querySelector('.select.action')?.addEventListener('click', handleSelect);
+    panelElem.querySelector('.title.panel > .labels > .compensation')?.addEventListener('click', handleLatencyCompensation);
     window.addEventListener('keydown', keyDown);
     window.addEventListener('keyup', keyUp);
-}
\ No newline at end of file
+}
+
+// Co-host links directly connect to the room.
+const cohost = new URLSearchParams(window.location.search);
+if (roomPlaceholder && cohost.has('host') && cohost.has('secret')) {
+    const [id, secret] = [cohost.get('host'), cohost.get('secret')];
+    run('host', { id, name: roomPlaceholder, secret, cohost: true }, `${location.origin.replace(/^http/, 'ws')}/rooms/${id}/host?secret=${encodeURIComponent(secret)}`, document.querySelector('.host.panel'));
+}
@@ -0,0 +1,46 @@
+use qrcode::{render::svg, Color, QrCode};
+
+use crate::error::Error;
+
+// In modules, as recommended by the specification.
+const QUIET_ZONE: usize = 4;
+const PNG_MODULE_SIZE: usize = 8;
+const SVG_MIN_SIZE: u32 = 256;
+
+pub fn svg(data: &str) -> Result<String, Error> {
+    Ok(QrCode::new(data.as_bytes())
+        .map_err(|_| Error::QrCodeDataTooLong)?
+        .render::<svg::Color>()
+        .min_dimensions(SVG_MIN_SIZE, SVG_MIN_SIZE)
+        .build())
+}
+
+pub fn png(data: &str) -> Result<Vec<u8>, Error> {
+    let code = QrCode::new(data.as_bytes()).map_err(|_| Error::QrCodeDataTooLong)?;
+    let width = code.width();
+    let size = (width + 2 * QUIET_ZONE) * PNG_MODULE_SIZE;
+
+    let mut pixels = vec![u8::MAX; size * size];
+    for (i, _) in code
+        .to_colors()
+        .iter()
+        .enumerate()
+        .filter(|(_, color)| **color == Color::Dark)
+    {
+        let x = (i % width + QUIET_ZONE) * PNG_MODULE_SIZE;
+        let y = (i / width + QUIET_ZONE) * PNG_MODULE_SIZE;
+        for row in y..y + PNG_MODULE_SIZE {
+            pixels[row * size + x..row * size + x + PNG_MODULE_SIZE].fill(0);
+        }
+    }
+
+    let mut image = Vec::new();
+    let mut encoder = png::Encoder::new(&mut image, size as u32, size as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels))
+        .expect("png encoding failed");
+    Ok(image)
+}
@@ -1,31 +1,83 @@
-use std::{collections::HashMap, sync::Weak, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    sync::Weak,
+    time::Duration,
+};
 
 use axum::extract::ws::WebSocket;
 use log::{as_display, info, warn};
+use rand::Rng;
+use serde::Serialize;
 use tokio::{sync::Mutex, task::JoinHandle, time};
 use ulid::Ulid;
 
-use crate::{error::Error, room::Room, utils};
+use crate::{
+    access::Access,
+    error::Error,
+    handshake::Capabilities,
+    packet::Settings,
+    room::{HostRole, Room, RoomConfig},
+    utils,
+};
 
 const ROOM_NAME_MIN_LEN: usize = 3;
+const ROOM_NAME_MAX_LEN: usize = 64;
 const RESERVATION_TIMEOUT: Duration = Duration::from_secs(15);
+const JOIN_CODE_LEN: usize = 6;
+
+/// Error refusing a WebSocket connection, along with the socket used to tell
+/// the client before closing it.
+pub type Refusal = (Error, Box<WebSocket>);
 
-#[derive(Default)]
 pub struct Registry {
+    room_config: RoomConfig,
     pending_rooms: HashMap<Ulid, PendingRoom>,
     pending_rooms_name_mapping: HashMap<Box<str>, Ulid>,
     rooms: HashMap<Ulid, Room>,
     rooms_name_mapping: HashMap<Box<str>, Ulid>,
+    // Join codes of both pending and live rooms.
+    codes: HashMap<Box<str>, Ulid>,
+    public_rooms: HashSet<Ulid>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicRoom {
+    id: Ulid,
+    name: Box<str>,
+    participant_count: usize,
+    // In seconds.
+    age: u64,
+    password_protected: bool,
 }
 
 impl Registry {
+    pub fn new(room_config: RoomConfig) -> Self {
+        Self {
+            room_config,
+            pending_rooms: HashMap::new(),
+            pending_rooms_name_mapping: HashMap::new(),
+            rooms: HashMap::new(),
+            rooms_name_mapping: HashMap::new(),
+            codes: HashMap::new(),
+            public_rooms: HashSet::new(),
+        }
+    }
+
     pub async fn reserve(
         &mut self,
         name: &str,
+        access: Access,
+        public: bool,
         weak_self: Weak<Mutex<Self>>,
-    ) -> Result<(Ulid, Box<str>), Error> {
-        let search_sanitized = utils::sanitize_for_search(name);
-        if search_sanitized.len() < ROOM_NAME_MIN_LEN {
+    ) -> Result<(Ulid, Box<str>, Box<str>, Box<str>), Error> {
+        let name = utils::sanitize(name);
+        if name.chars().count() > ROOM_NAME_MAX_LEN {
+            return Err(Error::RoomNameTooLong);
+        }
+        let search_sanitized = utils::sanitize_for_search(&name);
+        if search_sanitized.chars().count() < ROOM_NAME_MIN_LEN {
             return Err(Error::RoomNameTooShort);
         }
 
@@ -38,30 +90,153 @@ impl Registry {
         }
 
         let id = Ulid::new();
-        let name = utils::sanitize(name).to_owned().into_boxed_str();
+        let host_secret = utils::generate_token();
+        let code = self.generate_code();
 
         assert!(self
             .pending_rooms
-            .insert(id, PendingRoom::new(id, name.clone(), weak_self),)
+            .insert(
+                id,
+                PendingRoom::new(
+                    id,
+                    name.clone(),
+                    host_secret.clone(),
+                    access,
+                    public,
+                    weak_self
+                )
+            )
             .is_none());
         assert!(self
             .pending_rooms_name_mapping
             .insert(search_sanitized, id)
             .is_none());
+        self.codes.insert(code.clone(), id);
 
         info!(id = as_display!(id), room = as_display!(name); "room reserved");
-        Ok((id, name))
+        Ok((id, name, host_secret, code))
+    }
+
+    /// Generates a numeric code unused by other pending and live rooms.
+    fn generate_code(&self) -> Box<str> {
+        let mut rng = rand::thread_rng();
+        loop {
+            let code = (0..JOIN_CODE_LEN)
+                .map(|_| char::from(b'0' + rng.gen_range(0..10)))
+                .collect::<String>()
+                .into_boxed_str();
+            if !self.codes.contains_key(&code) {
+                return code;
+            }
+        }
+    }
+
+    /// Co-hosts can only join once the room is created by its owner.
+    pub fn check_host_secret(&self, id: Ulid, secret: &str) -> Result<HostRole, Error> {
+        if let Some(pending_room) = self.pending_rooms.get(&id) {
+            if *pending_room.host_secret != *secret {
+                return Err(Error::HostSecretInvalid);
+            }
+            return Ok(HostRole::Owner);
+        }
+        let room = self.rooms.get(&id).ok_or(Error::RoomNotFound)?;
+        if *room.host_secret == *secret {
+            Ok(HostRole::Owner)
+        } else if *room.cohost_secret == *secret {
+            Ok(HostRole::CoHost)
+        } else {
+            Err(Error::HostSecretInvalid)
+        }
+    }
+
+    /// Checks a secret allowed to open a host connection, including the one
+    /// given to a participant promoted to owner.
+    pub fn check_host_connect(&self, id: Ulid, secret: &str) -> Result<(), Error> {
+        if self
+            .rooms
+            .get(&id)
+            .is_some_and(|room| room.promoted_secret.as_deref() == Some(secret))
+        {
+            return Ok(());
+        }
+        self.check_host_secret(id, secret).map(|_| ())
+    }
+
+    pub fn cohost_secret(&self, id: Ulid) -> Result<Box<str>, Error> {
+        Ok(self
+            .rooms
+            .get(&id)
+            .ok_or(Error::RoomNotFound)?
+            .cohost_secret
+            .clone())
     }
 
-    pub fn create(
+    /// Replaces the owner secret, returning the co-host one for the previous
+    /// owner.
+    pub fn transfer_ownership(
         &mut self,
         id: Ulid,
+        host_secret: Box<str>,
+    ) -> Result<Box<str>, Error> {
+        let room = self.rooms.get_mut(&id).ok_or(Error::RoomNotFound)?;
+        room.host_secret = host_secret;
+        room.promoted_secret = None;
+        Ok(room.cohost_secret.clone())
+    }
+
+    /// Sets the secret of a participant promoted to owner, replacing the owner
+    /// one when they connect as a host.
+    pub fn promote(&mut self, id: Ulid, secret: Box<str>) -> Result<(), Error> {
+        self.rooms
+            .get_mut(&id)
+            .ok_or(Error::RoomNotFound)?
+            .promoted_secret = Some(secret);
+        Ok(())
+    }
+
+    /// Creates the room if it's still pending, or adds the host socket to the
+    /// existing room.
+    pub fn host(
+        &mut self,
+        id: Ulid,
+        secret: &str,
         socket: WebSocket,
         weak_self: Weak<Mutex<Self>>,
-    ) -> Result<(), Error> {
-        let Some(pending_room) = self.pending_rooms.remove(&id) else {
-            return Err(Error::RoomNotFound);
+    ) -> Result<(), Refusal> {
+        if let Some(room) = self.rooms.get_mut(&id) {
+            if room.promoted_secret.as_deref() == Some(secret) {
+                room.host_secret = room
+                    .promoted_secret
+                    .take()
+                    .expect("missing promoted secret");
+                room.connect_promoted(socket, room.cohost_secret.clone());
+                return Ok(());
+            }
+        }
+        let role = match self.check_host_secret(id, secret) {
+            Ok(role) => role,
+            Err(err) => return Err((err, Box::new(socket))),
         };
+        if let Some(pending_room) = self.pending_rooms.remove(&id) {
+            self.create(id, pending_room, socket, weak_self);
+            return Ok(());
+        }
+        match self.rooms.get(&id) {
+            Some(room) => {
+                room.connect_host(socket, role);
+                Ok(())
+            }
+            None => Err((Error::RoomNotFound, Box::new(socket))),
+        }
+    }
+
+    fn create(
+        &mut self,
+        id: Ulid,
+        pending_room: PendingRoom,
+        socket: WebSocket,
+        weak_self: Weak<Mutex<Self>>,
+    ) {
         pending_room.cleanup.abort();
 
         let search_sanitized = utils::sanitize_for_search(&pending_room.name);
@@ -74,11 +249,22 @@ impl Registry {
             .rooms_name_mapping
             .insert(search_sanitized, id)
             .is_none());
+        if pending_room.public {
+            self.public_rooms.insert(id);
+        }
         info!(id = as_display!(id), room = as_display!(pending_room.name); "room created");
-        self.rooms
-            .insert(id, Room::new(id, pending_room.name, socket, weak_self));
-
-        Ok(())
+        self.rooms.insert(
+            id,
+            Room::new(
+                id,
+                pending_room.name,
+                pending_room.host_secret,
+                pending_room.access,
+                socket,
+                self.room_config,
+                weak_self,
+            ),
+        );
     }
 
     pub fn remove(&mut self, id: Ulid, name: Box<str>) {
@@ -88,33 +274,197 @@ impl Registry {
                 .remove(&utils::sanitize_for_search(&name)),
             Some(id)
         );
+        self.codes.retain(|_, room_id| *room_id != id);
+        self.public_rooms.remove(&id);
         info!(id = as_display!(id), room = as_display!(name); "room removed");
     }
 
-    pub fn find_room(&self, name: &str) -> Result<(Ulid, Box<str>), Error> {
-        self.rooms_name_mapping
+    pub fn find_room(
+        &self,
+        name: &str,
+        password: Option<&str>,
+        invite: Option<&str>,
+    ) -> Result<(Ulid, Box<str>), Error> {
+        let (id, room) = self
+            .rooms_name_mapping
             .get(&utils::sanitize_for_search(name))
-            .copied()
-            .and_then(|id| self.rooms.get(&id).map(|r| (id, r.name.clone())))
-            .ok_or(Error::RoomNotFound)
+            .and_then(|id| self.rooms.get(id).map(|r| (*id, r)))
+            .ok_or(Error::RoomNotFound)?;
+        room.access.check(password, invite)?;
+        Ok((id, room.name.clone()))
+    }
+
+    /// Spaces and dashes are ignored, so codes can be typed as displayed.
+    pub fn find_room_by_code(
+        &self,
+        code: &str,
+        password: Option<&str>,
+        invite: Option<&str>,
+    ) -> Result<(Ulid, Box<str>), Error> {
+        let code = code
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '-')
+            .collect::<String>();
+        let (id, room) = self
+            .codes
+            .get(code.as_str())
+            .and_then(|id| self.rooms.get(id).map(|r| (*id, r)))
+            .ok_or(Error::RoomNotFound)?;
+        room.access.check(password, invite)?;
+        Ok((id, room.name.clone()))
+    }
+
+    /// Lists public rooms, newest first. Invite only rooms are never listed.
+    pub fn public_rooms(&self) -> Vec<PublicRoom> {
+        let mut rooms = self
+            .public_rooms
+            .iter()
+            .filter_map(|id| self.rooms.get(id).map(|room| (*id, room)))
+            .filter(|(_, room)| !room.access.invite_only())
+            .map(|(id, room)| PublicRoom {
+                id,
+                name: room.name.clone(),
+                participant_count: room.participant_count(),
+                age: room.age().as_secs(),
+                password_protected: room.access.password_protected(),
+            })
+            .collect::<Vec<_>>();
+        rooms.sort_by_key(|room| room.age);
+        rooms
+    }
+
+    pub fn room_name(&self, id: Ulid) -> Result<Box<str>, Error> {
+        Ok(self.rooms.get(&id).ok_or(Error::RoomNotFound)?.name.clone())
+    }
+
+    pub fn check_access(
+        &self,
+        id: Ulid,
+        password: Option<&str>,
+        invite: Option<&str>,
+    ) -> Result<(), Error> {
+        self.rooms
+            .get(&id)
+            .ok_or(Error::RoomNotFound)?
+            .access
+            .check(password, invite)
+    }
+
+    pub fn create_invite(&self, id: Ulid) -> Result<Box<str>, Error> {
+        Ok(self
+            .rooms
+            .get(&id)
+            .ok_or(Error::RoomNotFound)?
+            .access
+            .create_invite())
+    }
+
+    pub fn revoke_invite(&mut self, id: Ulid, invite: &str) -> Result<(), Error> {
+        self.rooms
+            .get_mut(&id)
+            .ok_or(Error::RoomNotFound)?
+            .access
+            .revoke_invite(invite)
+    }
+
+    pub fn check_ban(&self, id: Ulid, session: Option<&str>, ip: IpAddr) -> Result<(), Error> {
+        self.rooms
+            .get(&id)
+            .ok_or(Error::RoomNotFound)?
+            .access
+            .check_ban(session, ip)
     }
 
-    pub fn join_room(&self, id: Ulid, socket: WebSocket, name: Box<str>) -> Result<(), Error> {
+    pub fn check_username(&self, id: Ulid, name: &str, session: Option<&str>) -> Result<(), Error> {
         self.rooms
             .get(&id)
             .ok_or(Error::RoomNotFound)?
-            .join(socket, name);
+            .usernames
+            .read()
+            .expect("usernames lock poisoned")
+            .check(name, session)
+    }
+
+    pub fn ban(&mut self, id: Ulid, session: Box<str>, ip: Option<IpAddr>) -> Result<(), Error> {
+        self.rooms
+            .get_mut(&id)
+            .ok_or(Error::RoomNotFound)?
+            .access
+            .ban(session, ip);
         Ok(())
     }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn join_room(
+        &self,
+        id: Ulid,
+        socket: WebSocket,
+        name: Box<str>,
+        session: Option<Box<str>>,
+        team: Option<Box<str>>,
+        ip: IpAddr,
+        capabilities: Capabilities,
+    ) -> Result<(), Refusal> {
+        let Some(room) = self.rooms.get(&id) else {
+            return Err((Error::RoomNotFound, Box::new(socket)));
+        };
+        let checked = room.access.check_ban(session.as_deref(), ip).and_then(|_| {
+            room.usernames
+                .read()
+                .expect("usernames lock poisoned")
+                .check(&name, session.as_deref())
+        });
+        if let Err(err) = checked {
+            return Err((err, Box::new(socket)));
+        }
+        room.join(socket, name, session, team, ip, capabilities);
+        Ok(())
+    }
+
+    pub fn room_config(&self) -> RoomConfig {
+        self.room_config
+    }
+
+    /// Settings of a pending or live room, sent to clients during the handshake.
+    pub fn settings(&self, id: Ulid) -> Result<Settings, Error> {
+        let (access, public) = match self.pending_rooms.get(&id) {
+            Some(pending_room) => (&pending_room.access, pending_room.public),
+            None => (
+                &self.rooms.get(&id).ok_or(Error::RoomNotFound)?.access,
+                self.public_rooms.contains(&id),
+            ),
+        };
+        Ok(self.room_config.settings(access, public))
+    }
+
+    pub fn spectate(&self, id: Ulid, socket: WebSocket) -> Result<(), Refusal> {
+        match self.rooms.get(&id) {
+            Some(room) => {
+                room.spectate(socket);
+                Ok(())
+            }
+            None => Err((Error::RoomNotFound, Box::new(socket))),
+        }
+    }
 }
 
 struct PendingRoom {
     name: Box<str>,
+    host_secret: Box<str>,
+    access: Access,
+    public: bool,
     cleanup: JoinHandle<()>,
 }
 
 impl PendingRoom {
-    fn new(id: Ulid, name: Box<str>, weak_self: Weak<Mutex<Registry>>) -> Self {
+    fn new(
+        id: Ulid,
+        name: Box<str>,
+        host_secret: Box<str>,
+        access: Access,
+        public: bool,
+        weak_self: Weak<Mutex<Registry>>,
+    ) -> Self {
         let name_ref = name.clone();
         let cleanup_fut = tokio::spawn(async move {
             time::sleep(RESERVATION_TIMEOUT).await;
@@ -135,12 +485,16 @@ impl PendingRoom {
                     .remove(&search_sanitized),
                 Some(id)
             );
+            registry_lock.codes.retain(|_, room_id| *room_id != id);
 
             info!(id = as_display!(id), room = as_display!(name_ref); "room reservation expired");
         });
 
         Self {
             name,
+            host_secret,
+            access,
+            public,
             cleanup: cleanup_fut,
         }
     }
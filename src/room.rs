@@ -1,309 +1,2519 @@
 use std::{
-    sync::{Arc, Weak},
-    time::Instant,
+    collections::HashMap,
+    net::IpAddr,
+    ops::ControlFlow,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, RwLock, Weak,
+    },
+    time::{Duration, Instant},
 };
 
 use axum::extract::ws::{Message as WsMessage, WebSocket};
-use futures::{SinkExt, StreamExt};
-use tokio::sync::{
-    broadcast, broadcast::Sender as BroadcastSender, mpsc, mpsc::Sender as MpscSender, Mutex,
+use futures::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
+};
+use log::{as_debug, as_display, info};
+use tokio::{
+    sync::{
+        broadcast,
+        broadcast::Sender as BroadcastSender,
+        mpsc,
+        mpsc::{Receiver as MpscReceiver, Sender as MpscSender},
+        oneshot, Mutex,
+    },
+    task::JoinHandle,
+    time,
 };
 use ulid::Ulid;
 
 use crate::{
-    packet::{PacketIn, PacketOut},
+    access::Access,
+    error::Error,
+    handshake::{Capabilities, REPORTED_TIME},
+    packet::{
+        Buzz, ErrorCode, HostEntry, PacketIn, PacketOut, RosterEntry, Score, Settings, Summary,
+        TeamInfo, Verdict,
+    },
     registry::Registry,
+    utils,
 };
 
+const USERNAME_MIN_LEN: usize = 2;
+const USERNAME_MAX_LEN: usize = 32;
+const TEAM_NAME_MAX_LEN: usize = 32;
 const CHANNEL_SIZE: usize = 1024;
+const TIMER_TICK: Duration = Duration::from_secs(1);
+const MAX_TIMER_DURATION: Duration = Duration::from_secs(6 * 60 * 60);
+// Participants delaying their pongs could otherwise get ahead of everyone.
+const MAX_LATENCY_COMPENSATION: Duration = Duration::from_millis(250);
+// Accepted error of a reported buzz time, on top of the estimated one way
+// latency.
+const REPORTED_TIME_TOLERANCE: Duration = Duration::from_millis(50);
+// Invalid packets tolerated from a connection during a period before closing it.
+const MAX_PACKET_ERRORS: usize = 10;
+const PACKET_ERRORS_PERIOD: Duration = Duration::from_secs(60);
+
+pub fn sanitize_username(name: &str) -> Result<Box<str>, Error> {
+    let name = utils::sanitize(name);
+    match name.chars().count() {
+        len if len < USERNAME_MIN_LEN => Err(Error::UsernameTooShort),
+        len if len > USERNAME_MAX_LEN => Err(Error::UsernameTooLong),
+        _ => Ok(name),
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RoomConfig {
+    pub host_grace_period: Duration,
+    pub false_start_penalty: Duration,
+    pub settling_window: Duration,
+    pub heartbeat_interval: Duration,
+    pub heartbeat_timeout: Duration,
+}
+
+impl RoomConfig {
+    pub fn settings(&self, access: &Access, public: bool) -> Settings {
+        Settings {
+            password_protected: access.password_protected(),
+            invite_only: access.invite_only(),
+            public,
+            host_grace_period: self.host_grace_period.as_secs(),
+            false_start_penalty: self.false_start_penalty.as_secs(),
+            settling_window: self.settling_window.as_millis() as u64,
+            heartbeat_interval: self.heartbeat_interval.as_secs(),
+            heartbeat_timeout: self.heartbeat_timeout.as_secs(),
+        }
+    }
+
+    /// Round trip times are measured using the pongs answering the heartbeat.
+    fn measures_rtt(&self) -> bool {
+        !self.heartbeat_interval.is_zero()
+    }
+
+    /// Longest silence allowed on a connection, pongs included.
+    fn heartbeat_deadline(&self) -> Duration {
+        if self.heartbeat_interval.is_zero() {
+            return Duration::MAX;
+        }
+        self.heartbeat_interval + self.heartbeat_timeout
+    }
+}
+
+/// Reads the next message of the connection, or `None` if it's closed or
+/// didn't send anything in time.
+async fn next_message(rx: &mut SplitStream<WebSocket>, deadline: Duration) -> Option<WsMessage> {
+    match time::timeout(deadline, rx.next()).await {
+        Ok(Some(Ok(WsMessage::Close(_)))) => None,
+        Ok(Some(Ok(msg))) => Some(msg),
+        Ok(Some(Err(_)) | None) | Err(_) => None,
+    }
+}
+
+/// Counts the invalid packets sent by a connection, so a misbehaving client
+/// gets errors back and is only disconnected when it keeps going.
+struct PacketErrors {
+    count: usize,
+    since: Instant,
+}
+
+impl PacketErrors {
+    fn new() -> Self {
+        Self {
+            count: 0,
+            since: Instant::now(),
+        }
+    }
+
+    /// Returns whether the connection should be closed.
+    fn record(&mut self) -> bool {
+        if self.since.elapsed() >= PACKET_ERRORS_PERIOD {
+            self.count = 0;
+            self.since = Instant::now();
+        }
+        self.count += 1;
+        self.count > MAX_PACKET_ERRORS
+    }
+}
+
+/// Press time of a buzz, from the instant the round start marker was sent and
+/// the time elapsed since its reception reported by the participant. The buzz
+/// should have been received about one way latency later, timestamps outside of
+/// this bound being implausible.
+fn reported_time(
+    round_start: Instant,
+    elapsed: Duration,
+    one_way: Duration,
+    received: Instant,
+) -> Option<Instant> {
+    let reported = round_start.checked_add(one_way + elapsed)?;
+    if reported.saturating_duration_since(received) > REPORTED_TIME_TOLERANCE
+        || received.saturating_duration_since(reported) > one_way + REPORTED_TIME_TOLERANCE
+    {
+        return None;
+    }
+    Some(reported.min(received))
+}
+
+/// Round trip time of a heartbeat, whose payload is the number of milliseconds
+/// elapsed since the creation of the room when the ping was sent.
+fn heartbeat_rtt(created: Instant, payload: &[u8]) -> Option<Duration> {
+    let sent = u64::from_be_bytes(payload.try_into().ok()?);
+    created.elapsed().checked_sub(Duration::from_millis(sent))
+}
 
 #[derive(Debug)]
 pub struct Room {
     pub name: Box<str>,
+    pub host_secret: Box<str>,
+    // Given by the owner to other hosts.
+    pub cohost_secret: Box<str>,
+    // Given to a participant promoted to owner, until they connect as a host.
+    pub promoted_secret: Option<Box<str>>,
+    pub access: Access,
+    pub usernames: Arc<RwLock<Usernames>>,
+    participant_count: Arc<AtomicUsize>,
+    created: Instant,
+    config: RoomConfig,
     main: MpscSender<RoomMessage>,
     broadcast: BroadcastSender<BroadcastMessage>,
 }
 
 impl Room {
-    pub fn new(id: Ulid, name: Box<str>, host: WebSocket, registry: Weak<Mutex<Registry>>) -> Self {
-        let (main_tx, mut main_rx) = mpsc::channel::<RoomMessage>(CHANNEL_SIZE);
+    pub fn new(
+        id: Ulid,
+        name: Box<str>,
+        host_secret: Box<str>,
+        access: Access,
+        host: WebSocket,
+        config: RoomConfig,
+        registry: Weak<Mutex<Registry>>,
+    ) -> Self {
+        let (main_tx, main_rx) = mpsc::channel::<RoomMessage>(CHANNEL_SIZE);
         let (broadcast_tx, _broadcast_rx) = broadcast::channel(CHANNEL_SIZE);
-        let (mut host_tx, mut host_rx) = host.split();
-
-        let self_name = name.clone();
-        let self_broadcast_tx = broadcast_tx.clone();
-        tokio::spawn(async move {
-            let mut participants_count = 0;
-            let mut run = Run::new();
-
-            loop {
-                let msg = main_rx.recv().await.expect("main channel closed");
-
-                match msg {
-                    RoomMessage::ParticipantJoin => {
-                        participants_count += 1;
-                        let packet = WsMessage::from(PacketOut::ParticipantCount {
-                            count: participants_count,
-                        });
-                        host_tx.send(packet.clone()).await.expect("send failed");
-                        _ = self_broadcast_tx.send(BroadcastMessage::All(packet));
-                    }
-                    RoomMessage::Buzzed(buzzer, timestamp) => {
-                        let buzz_result = run.buzz(buzzer.id, timestamp);
-                        let timestamp_diff = match buzz_result {
-                            BuzzResult::Already => continue,
-                            BuzzResult::First => None,
-                            BuzzResult::TimeDifference(diff) => Some(diff),
-                        };
-                        host_tx
-                            .send(
-                                PacketOut::Buzzed {
-                                    id: buzzer.id,
-                                    name: buzzer.name.clone(),
-                                    timestamp_diff,
-                                }
-                                .into(),
-                            )
-                            .await
-                            .expect("send failed");
-                        if matches!(buzz_result, BuzzResult::First) {
-                            _ = self_broadcast_tx.send(BroadcastMessage::Single(
-                                run.first_unchecked(),
-                                Arc::new(WsMessage::from(PacketOut::Select { id: None })),
-                            ));
-                        }
-                    }
-                    RoomMessage::SelectNext => {
-                        let Some((to_clear, to_notify)) = run.select_next() else {
-                            continue;
-                        };
-                        _ = self_broadcast_tx.send(BroadcastMessage::Single(
-                            to_clear,
-                            Arc::new(WsMessage::from(PacketOut::Deselect)),
-                        ));
-                        _ = self_broadcast_tx.send(BroadcastMessage::Single(
-                            to_notify,
-                            Arc::new(WsMessage::from(PacketOut::Select { id: None })),
-                        ));
-                        host_tx
-                            .send(WsMessage::from(PacketOut::Select {
-                                id: Some(to_notify),
-                            }))
-                            .await
-                            .expect("send failed");
-                    }
-                    RoomMessage::Clear => {
-                        run = Run::new();
-                        _ = self_broadcast_tx
-                            .send(BroadcastMessage::All(WsMessage::from(PacketOut::Clear)));
-                    }
-                    RoomMessage::ParticipantLeft => {
-                        participants_count -= 1;
-                        let packet = WsMessage::from(PacketOut::ParticipantCount {
-                            count: participants_count,
-                        });
-                        host_tx.send(packet.clone()).await.expect("send failed");
-                        _ = self_broadcast_tx.send(BroadcastMessage::All(packet));
-                    }
-                    RoomMessage::HostLeft => {
-                        registry
-                            .upgrade()
-                            .expect("registry deallocated")
-                            .lock()
-                            .await
-                            .remove(id, self_name);
-                        // If the host was alone, the broadcast channel is already partially closed.
-                        _ = self_broadcast_tx
-                            .send(BroadcastMessage::All(WsMessage::from(PacketOut::HostLeft)));
-                        return;
-                    }
-                }
-            }
-        });
+        let created = Instant::now();
 
-        let self_main_tx = main_tx.clone();
-        tokio::spawn(async move {
-            loop {
-                match host_rx.next().await {
-                    Some(Ok(msg)) => match PacketIn::try_from(msg) {
-                        Ok(PacketIn::Clear) => {
-                            self_main_tx
-                                .send(RoomMessage::Clear)
-                                .await
-                                .expect("send failed");
-                        }
-                        Ok(PacketIn::SelectNext) => {
-                            self_main_tx
-                                .send(RoomMessage::SelectNext)
-                                .await
-                                .expect("send failed");
-                        }
-                        Ok(_) | Err(_) => {
-                            _ = self_main_tx.send(RoomMessage::HostLeft).await;
-                            return;
-                        }
-                    },
-                    Some(Err(_)) | None => {
-                        _ = self_main_tx.send(RoomMessage::HostLeft).await;
-                        return;
-                    }
-                }
-            }
-        });
+        let usernames = Arc::new(RwLock::new(Usernames::new()));
+        let participant_count = Arc::new(AtomicUsize::new(0));
+        let host = HostConnection::new(
+            host,
+            HostRole::Owner,
+            config.heartbeat_deadline(),
+            main_tx.clone(),
+        );
+        let actor = RoomActor {
+            id,
+            name: name.clone(),
+            config,
+            registry,
+            main: main_tx.clone(),
+            broadcast: broadcast_tx.clone(),
+            last_host_left: host.id,
+            hosts: HashMap::from([(host.id, host)]),
+            participants: Participants::new(Arc::clone(&usernames), Arc::clone(&participant_count)),
+            run: Run::new(false),
+            armed: true,
+            false_starts: HashMap::new(),
+            timer: None,
+            settling: None,
+            promoted: None,
+            scoreboard: Scoreboard::new(),
+            teams: Teams::new(),
+            created,
+        };
+        tokio::spawn(actor.run(main_rx));
 
         Self {
             name,
+            host_secret,
+            cohost_secret: utils::generate_token(),
+            promoted_secret: None,
+            access,
+            usernames,
+            participant_count,
+            created,
+            config,
             main: main_tx,
             broadcast: broadcast_tx,
         }
     }
 
-    pub fn join(&self, socket: WebSocket, name: Box<str>) {
-        let id = Ulid::new();
-        let participant = Arc::new(Participant { id, name });
+    pub fn participant_count(&self) -> usize {
+        self.participant_count.load(Ordering::Relaxed)
+    }
+
+    pub fn age(&self) -> Duration {
+        self.created.elapsed()
+    }
 
+    pub fn connect_host(&self, socket: WebSocket, role: HostRole) {
+        let main_tx = self.main.clone();
+        tokio::spawn(async move {
+            _ = main_tx
+                .send(RoomMessage::HostConnect(Box::new(socket), role))
+                .await;
+        });
+    }
+
+    pub fn connect_promoted(&self, socket: WebSocket, cohost_secret: Box<str>) {
+        let main_tx = self.main.clone();
+        tokio::spawn(async move {
+            _ = main_tx
+                .send(RoomMessage::PromotedConnect(
+                    Box::new(socket),
+                    cohost_secret,
+                ))
+                .await;
+        });
+    }
+
+    /// Forwards the packets sent to the host to a read-only socket. Spectators
+    /// aren't participants, so they can't buzz and aren't counted.
+    pub fn spectate(&self, socket: WebSocket) {
         let (mut tx, mut rx) = socket.split();
         let main_tx = self.main.clone();
         let mut broadcast_rx = self.broadcast.subscribe();
+        let deadline = self.config.heartbeat_deadline();
 
-        let rx_handle = tokio::spawn(async move {
-            loop {
-                match broadcast_rx.recv().await {
-                    Ok(msg) => {
-                        if msg.is_target(&id) && tx.send(msg.inner()).await.is_err() {
+        tokio::spawn(async move {
+            let id = Ulid::new();
+            if main_tx.send(RoomMessage::SpectatorJoin(id)).await.is_err() {
+                return;
+            }
+
+            let rx_handle = tokio::spawn(async move {
+                loop {
+                    match broadcast_rx.recv().await {
+                        Ok(msg) => {
+                            if !msg.is_spectator_target(&id) {
+                                continue;
+                            }
+                            if tx.send(msg.inner()).await.is_err() {
+                                return;
+                            }
+                        }
+                        Err(_err) => {
+                            _ = tx.close().await;
                             return;
                         }
                     }
-                    Err(_err) => {
-                        _ = tx.close().await;
-                        return;
-                    }
                 }
-            }
+            });
+            // Spectators aren't allowed to send anything but pongs.
+            while let Some(WsMessage::Ping(_) | WsMessage::Pong(_)) =
+                next_message(&mut rx, deadline).await
+            {}
+            rx_handle.abort();
         });
+    }
+
+    pub fn join(
+        &self,
+        socket: WebSocket,
+        name: Box<str>,
+        session: Option<Box<str>>,
+        team: Option<Box<str>>,
+        ip: IpAddr,
+        capabilities: Capabilities,
+    ) {
+        let (mut tx, mut rx) = socket.split();
+        let main_tx = self.main.clone();
+        let mut broadcast_rx = self.broadcast.subscribe();
+        let deadline = self.config.heartbeat_deadline();
+        let created = self.created;
+        // Older clients may send their buzz time without knowing its semantic.
+        let reports_time = capabilities.contains(REPORTED_TIME);
+
         tokio::spawn(async move {
-            if main_tx.send(RoomMessage::ParticipantJoin).await.is_err() {
-                rx_handle.abort();
+            let connection = Ulid::new();
+            let (reply_tx, reply_rx) = oneshot::channel();
+            if main_tx
+                .send(RoomMessage::ParticipantJoin {
+                    name,
+                    session,
+                    team,
+                    ip,
+                    connection,
+                    reply: reply_tx,
+                })
+                .await
+                .is_err()
+            {
                 return;
             }
-            loop {
-                match rx.next().await {
-                    Some(Ok(msg)) => match PacketIn::try_from(msg) {
-                        Ok(PacketIn::Buzz) => {
-                            if main_tx
-                                .send(RoomMessage::Buzzed(
-                                    Arc::clone(&participant),
-                                    Instant::now(),
-                                ))
-                                .await
-                                .is_err()
-                            {
-                                rx_handle.abort();
+            let participant = match reply_rx.await {
+                Ok(Ok(participant)) => participant,
+                Ok(Err(err)) => {
+                    _ = tx
+                        .send(WsMessage::from(PacketOut::Error {
+                            code: ErrorCode::JoinRefused,
+                            message: err.to_string().into_boxed_str(),
+                        }))
+                        .await;
+                    _ = tx.close().await;
+                    return;
+                }
+                Err(_) => {
+                    _ = tx.close().await;
+                    return;
+                }
+            };
+            let id = participant.id;
+
+            let rx_handle = tokio::spawn(async move {
+                loop {
+                    match broadcast_rx.recv().await {
+                        Ok(msg) => {
+                            if !msg.is_target(&id) {
+                                continue;
+                            }
+                            let kicked = matches!(msg, BroadcastMessage::Kick(..));
+                            if tx.send(msg.inner()).await.is_err() {
+                                return;
+                            }
+                            if kicked {
+                                _ = tx.close().await;
                                 return;
                             }
                         }
-                        Ok(_) | Err(_) => {
-                            rx_handle.abort();
-                            _ = main_tx.send(RoomMessage::ParticipantLeft).await;
+                        Err(_err) => {
+                            _ = tx.close().await;
                             return;
                         }
-                    },
-                    Some(Err(_)) | None => {
-                        rx_handle.abort();
-                        _ = main_tx.send(RoomMessage::ParticipantLeft).await;
-                        return;
                     }
                 }
+            });
+            let mut errors = PacketErrors::new();
+            loop {
+                let msg = match next_message(&mut rx, deadline).await {
+                    Some(WsMessage::Pong(payload)) => match heartbeat_rtt(created, &payload) {
+                        Some(rtt) => RoomMessage::Latency(id, rtt),
+                        None => continue,
+                    },
+                    Some(WsMessage::Ping(_)) => continue,
+                    Some(msg) => match PacketIn::try_from(msg) {
+                        Ok(PacketIn::Buzz { round, elapsed }) => RoomMessage::Buzzed(
+                            Arc::clone(&participant),
+                            Instant::now(),
+                            round
+                                .zip(elapsed.map(Duration::from_millis))
+                                .filter(|_| reports_time),
+                        ),
+                        Ok(_) | Err(_) if errors.record() => break,
+                        Ok(_) => RoomMessage::Rejected {
+                            to: id,
+                            code: ErrorCode::UnexpectedPacket,
+                            message: "this packet isn't expected from a participant".into(),
+                        },
+                        Err(message) => RoomMessage::Rejected {
+                            to: id,
+                            code: ErrorCode::MalformedPacket,
+                            message,
+                        },
+                    },
+                    None => break,
+                };
+                if main_tx.send(msg).await.is_err() {
+                    rx_handle.abort();
+                    return;
+                }
             }
+            rx_handle.abort();
+            _ = main_tx
+                .send(RoomMessage::ParticipantLeft(id, connection))
+                .await;
         });
     }
 }
 
-#[derive(Debug)]
-struct Run {
-    buzzed: Vec<(Ulid, Instant)>,
-    selection: usize,
+struct RoomActor {
+    id: Ulid,
+    name: Box<str>,
+    config: RoomConfig,
+    registry: Weak<Mutex<Registry>>,
+    main: MpscSender<RoomMessage>,
+    broadcast: BroadcastSender<BroadcastMessage>,
+    hosts: HashMap<Ulid, HostConnection>,
+    // Id of the last host connection that left, used to discard outdated grace
+    // period expirations.
+    last_host_left: Ulid,
+    participants: Participants,
+    run: Run,
+    armed: bool,
+    // Instants until which participants who buzzed too early can't buzz.
+    false_starts: HashMap<Ulid, Instant>,
+    timer: Option<Timer>,
+    // Id and task of the settling window currently open, during which buzzes
+    // aren't announced as their order may still change.
+    settling: Option<(Ulid, JoinHandle<()>)>,
+    // Participant given the ownership, who hasn't connected as a host yet.
+    promoted: Option<Arc<Participant>>,
+    scoreboard: Scoreboard,
+    teams: Teams,
+    created: Instant,
 }
 
-impl Run {
-    fn new() -> Self {
-        Self {
-            buzzed: Vec::new(),
-            selection: 0,
+impl RoomActor {
+    async fn run(mut self, mut main_rx: MpscReceiver<RoomMessage>) {
+        self.send_host(self.host_state()).await;
+        self.send_teams().await;
+        let main_tx = self.main.clone();
+        let heartbeat_interval = self.config.heartbeat_interval;
+        let heartbeat = tokio::spawn(async move {
+            if heartbeat_interval.is_zero() {
+                return;
+            }
+            let mut interval = time::interval(heartbeat_interval);
+            loop {
+                interval.tick().await;
+                if main_tx.send(RoomMessage::Heartbeat).await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        // Last packet sent to everyone, depending on how the room got closed.
+        let farewell = loop {
+            let msg = main_rx.recv().await.expect("main channel closed");
+
+            match msg {
+                RoomMessage::ParticipantJoin {
+                    name,
+                    session,
+                    team,
+                    ip,
+                    connection,
+                    reply,
+                } => {
+                    self.participant_join(name, session, team, ip, connection, reply)
+                        .await
+                }
+                RoomMessage::SpectatorJoin(spectator_id) => self.spectator_join(spectator_id),
+                RoomMessage::Heartbeat => self.heartbeat().await,
+                RoomMessage::Latency(participant_id, rtt) => {
+                    self.latency(participant_id, rtt).await
+                }
+                RoomMessage::SetLatencyCompensation { from, enabled } => {
+                    self.set_latency_compensation(from, enabled).await
+                }
+                RoomMessage::Buzzed(buzzer, timestamp, reported) => {
+                    self.buzzed(buzzer, timestamp, reported).await
+                }
+                RoomMessage::SelectNext => self.select_next().await,
+                RoomMessage::Clear => self.clear().await,
+                RoomMessage::SetArmed(armed) => self.set_armed(armed).await,
+                RoomMessage::StartTimer { from, seconds } => self.start_timer(from, seconds).await,
+                RoomMessage::StopTimer => self.stop_timer().await,
+                RoomMessage::TimerTick(timer_id) => self.timer_tick(timer_id).await,
+                RoomMessage::SettlingWindowClosed(settling_id) => self.settle(settling_id).await,
+                RoomMessage::Judge(verdict) => self.judge(verdict).await,
+                RoomMessage::AdjustScore(participant_id, delta) => {
+                    if self.participants.contains(participant_id) {
+                        self.scoreboard.adjust(participant_id, delta);
+                        self.send_scoreboard().await;
+                    }
+                }
+                RoomMessage::ResetScores => {
+                    self.scoreboard.reset();
+                    self.send_scoreboard().await;
+                }
+                RoomMessage::ConfigureScoring {
+                    correct_points,
+                    incorrect_points,
+                } => {
+                    self.scoreboard.correct_points = correct_points;
+                    self.scoreboard.incorrect_points = incorrect_points;
+                    self.send_scoreboard().await;
+                }
+                RoomMessage::CreateTeam(name) => self.create_team(name).await,
+                RoomMessage::RenameTeam(team_id, name) => self.rename_team(team_id, name).await,
+                RoomMessage::AssignTeam(participant_id, team_id) => {
+                    self.assign_team(participant_id, team_id).await
+                }
+                RoomMessage::RenameParticipant(participant_id, name) => {
+                    self.rename_participant(participant_id, name).await
+                }
+                RoomMessage::Kick(participant_id, reason) => {
+                    self.kick(participant_id, reason, false).await
+                }
+                RoomMessage::Ban {
+                    participant,
+                    reason,
+                    ip,
+                } => self.ban(participant, reason, ip).await,
+                RoomMessage::TransferOwnership { from, to } => {
+                    self.transfer_ownership(from, to).await
+                }
+                RoomMessage::ParticipantLeft(participant_id, connection) => {
+                    self.participant_left(participant_id, connection).await
+                }
+                RoomMessage::HostConnect(socket, role) => self.host_connect(*socket, role).await,
+                RoomMessage::PromotedConnect(socket, cohost_secret) => {
+                    self.promoted_connect(*socket, cohost_secret).await
+                }
+                RoomMessage::HostLeft(connection_id) => {
+                    if self.host_left(connection_id).await.is_break() {
+                        info!(id = as_display!(self.id), room = as_display!(self.name); "host left, closing room");
+                        break PacketOut::HostLeft;
+                    }
+                }
+                RoomMessage::HostGracePeriodExpired(connection_id) => {
+                    if self.hosts.is_empty() && self.last_host_left == connection_id {
+                        info!(id = as_display!(self.id), room = as_display!(self.name); "host grace period expired, closing room");
+                        break PacketOut::HostLeft;
+                    }
+                }
+                RoomMessage::CloseRoom { from, reason } => match self.close_room(from, reason) {
+                    Some(packet) => break packet,
+                    None => {
+                        self.reject(
+                            from,
+                            ErrorCode::Unauthorized,
+                            "only the owner can close the room".into(),
+                        )
+                        .await
+                    }
+                },
+                RoomMessage::Rejected { to, code, message } => self.reject(to, code, message).await,
+            }
+        };
+
+        heartbeat.abort();
+        self.registry
+            .upgrade()
+            .expect("registry deallocated")
+            .lock()
+            .await
+            .remove(self.id, self.name.clone());
+        // If the host was alone, the broadcast channel is already partially closed.
+        let farewell = WsMessage::from(farewell);
+        _ = self.broadcast.send(BroadcastMessage::All(farewell.clone()));
+        for (_, mut host) in self.hosts.drain() {
+            host.send(farewell.clone()).await;
+            host.close().await;
         }
     }
 
-    fn buzz(&mut self, buzzer: Ulid, time: Instant) -> BuzzResult {
-        // Start from the back because it's likely the last participant spamming the
-        // buzzer.
-        if self.buzzed.iter().rev().any(|(b, _)| b == &buzzer) {
-            return BuzzResult::Already;
+    async fn participant_join(
+        &mut self,
+        name: Box<str>,
+        session: Option<Box<str>>,
+        team: Option<Box<str>>,
+        ip: IpAddr,
+        connection: Ulid,
+        reply: oneshot::Sender<Result<Arc<Participant>, Error>>,
+    ) {
+        let (participant, session, newly_connected) =
+            match self
+                .participants
+                .connect(name, session.as_deref(), ip, connection)
+            {
+                Ok(connected) => connected,
+                Err(err) => {
+                    _ = reply.send(Err(err));
+                    return;
+                }
+            };
+        // Resumed sessions keep the team they were in.
+        let team_changed = match team {
+            Some(team) if self.teams.team_of(participant.id).is_none() => {
+                match self.teams.find_or_create(team) {
+                    Some(team_id) => self.teams.assign(participant.id, Some(team_id)),
+                    None => false,
+                }
+            }
+            _ => false,
+        };
+        let round = self.run.mark_round_start();
+        self.send_single(
+            participant.id,
+            PacketOut::Welcome {
+                id: participant.id,
+                session,
+                armed: self.armed,
+                timer: self.timer.as_ref().map(Timer::remaining),
+                buzzed: self.run.has_buzzed(participant.id),
+                waiting: self.run.is_waiting(participant.id),
+                selected: self.run.selected() == Some(participant.id),
+                verdict: self.run.verdict(participant.id),
+                team: self.teams.name_of(participant.id),
+                round,
+            },
+        );
+        // Measures the round trip time of the connection right away, instead of
+        // waiting for the next heartbeat.
+        if self.config.measures_rtt() {
+            _ = self.broadcast.send(BroadcastMessage::Single(
+                participant.id,
+                Arc::new(WsMessage::Ping(self.heartbeat_payload())),
+            ));
         }
-        let res = if self.buzzed.is_empty() {
-            BuzzResult::First
+        let participant_id = participant.id;
+        let name = participant.name.clone();
+        _ = reply.send(Ok(participant));
+
+        if newly_connected {
+            self.send_host(PacketOut::ParticipantJoined {
+                id: participant_id,
+                name: name.clone(),
+            })
+            .await;
+            self.send_everyone(PacketOut::ParticipantCount {
+                count: self.participants.count(),
+            })
+            .await;
+            self.send_scoreboard().await;
         } else {
-            BuzzResult::TimeDifference((time - self.buzzed[0].1).as_millis() as u64)
+            self.send_single(participant_id, self.scoreboard.packet(&self.participants));
+        }
+        if team_changed {
+            self.send_teams().await;
+        }
+    }
+
+    /// Pings every connection at the WebSocket level, to detect the dead ones.
+    async fn heartbeat(&mut self) {
+        let payload = self.heartbeat_payload();
+        for host in self.hosts.values_mut() {
+            host.send(WsMessage::Ping(payload.clone())).await;
+        }
+        _ = self
+            .broadcast
+            .send(BroadcastMessage::All(WsMessage::Ping(payload)));
+    }
+
+    fn heartbeat_payload(&self) -> Vec<u8> {
+        (self.created.elapsed().as_millis() as u64)
+            .to_be_bytes()
+            .to_vec()
+    }
+
+    /// Compensation relies on the round trip times measured by the heartbeat.
+    async fn set_latency_compensation(&mut self, from: Ulid, enabled: bool) {
+        if enabled && !self.config.measures_rtt() {
+            self.reject(
+                from,
+                ErrorCode::InvalidValue,
+                "latency compensation requires the heartbeat to be enabled".into(),
+            )
+            .await;
+            return;
+        }
+        self.run.set_compensated(enabled);
+        self.send_host(self.host_state()).await;
+    }
+
+    async fn latency(&mut self, participant_id: Ulid, rtt: Duration) {
+        self.participants.record_rtt(participant_id, rtt);
+        let Some(rtt) = self.participants.rtt(participant_id) else {
+            return;
         };
-        self.buzzed.push((buzzer, time));
-        res
+        self.send_host(PacketOut::Latency {
+            id: participant_id,
+            rtt: rtt.as_millis() as u64,
+        })
+        .await;
     }
 
-    fn select_next(&mut self) -> Option<(Ulid, Ulid)> {
-        if self.buzzed.len() < 2 || self.selection == self.buzzed.len() - 1 {
+    /// Sends the current state of the room, as the host would receive it when
+    /// reconnecting.
+    fn spectator_join(&self, spectator_id: Ulid) {
+        self.send_single(spectator_id, self.host_state());
+        self.send_single(spectator_id, self.scoreboard.packet(&self.participants));
+        self.send_single(spectator_id, self.teams.packet());
+        self.send_single(spectator_id, self.participants.roster());
+    }
+
+    async fn buzzed(
+        &mut self,
+        buzzer: Arc<Participant>,
+        timestamp: Instant,
+        reported: Option<(Ulid, Duration)>,
+    ) {
+        // Kicked participants may keep their socket open for a while.
+        if !self.participants.is_connected(buzzer.id) {
+            return;
+        }
+        // The participant may have been renamed since the connection started.
+        let buzzer = self.participants.get(buzzer.id).unwrap_or(buzzer);
+        if !self.armed {
+            if !self.config.false_start_penalty.is_zero() {
+                self.false_starts
+                    .insert(buzzer.id, timestamp + self.config.false_start_penalty);
+                self.send_single(
+                    buzzer.id,
+                    PacketOut::FalseStart {
+                        penalty: self.config.false_start_penalty.as_millis() as u64,
+                    },
+                );
+            }
+            return;
+        }
+        if self
+            .false_starts
+            .get(&buzzer.id)
+            .is_some_and(|until| *until > timestamp)
+            || self.run.winner().is_some()
+        {
+            return;
+        }
+
+        let rtt = self.participants.rtt(buzzer.id);
+        let reported = reported
+            .and_then(|(round, elapsed)| self.reported_time(round, elapsed, rtt, timestamp));
+        let buzz_result = self.run.buzz(
+            Arc::clone(&buzzer),
+            self.teams.team_of(buzzer.id),
+            timestamp,
+            rtt,
+            reported,
+        );
+        if matches!(buzz_result, BuzzResult::Already) {
+            return;
+        }
+        if matches!(buzz_result, BuzzResult::First) && !self.config.settling_window.is_zero() {
+            self.open_settling_window();
+        }
+        if self.settling.is_some() {
+            return;
+        }
+        let timestamp_diff = match buzz_result {
+            BuzzResult::Already => return,
+            BuzzResult::First => None,
+            BuzzResult::TimeDifference(diff) => Some(diff),
+            BuzzResult::Overtook(overtaken) => {
+                if let Some(overtaken) = overtaken {
+                    self.send_single(overtaken, PacketOut::Deselect);
+                    self.send_single(buzzer.id, PacketOut::Select { id: None });
+                }
+                // The whole list is sent again as its order changed.
+                self.send_host(self.host_state()).await;
+                return;
+            }
+        };
+        self.send_host(PacketOut::Buzzed {
+            id: buzzer.id,
+            name: buzzer.name.clone(),
+            team: self.teams.name_of(buzzer.id),
+            timestamp_diff,
+            in_time: self.run.last_in_time(),
+            rtt: rtt.map(|rtt| rtt.as_millis() as u64),
+        })
+        .await;
+        // The first buzzer is selected, as well as late buzzers arriving after
+        // every previous one was judged incorrect.
+        if self.run.selected() == Some(buzzer.id) {
+            self.send_single(buzzer.id, PacketOut::Select { id: None });
+            if !matches!(buzz_result, BuzzResult::First) {
+                self.send_host(PacketOut::Select {
+                    id: Some(buzzer.id),
+                })
+                .await;
+            }
+        }
+    }
+
+    fn open_settling_window(&mut self) {
+        let id = Ulid::new();
+        let window = self.config.settling_window;
+        let main_tx = self.main.clone();
+        let task = tokio::spawn(async move {
+            time::sleep(window).await;
+            _ = main_tx.send(RoomMessage::SettlingWindowClosed(id)).await;
+        });
+        self.settling = Some((id, task));
+    }
+
+    /// Announces the buzzes received during the settling window, and selects
+    /// the first one.
+    async fn settle(&mut self, settling_id: Ulid) {
+        if self.settling.as_ref().map(|(id, _)| *id) != Some(settling_id) {
+            return;
+        }
+        self.settling = None;
+        self.send_host(self.host_state()).await;
+        if let Some(selected) = self.run.selected() {
+            self.send_single(selected, PacketOut::Select { id: None });
+        }
+    }
+
+    /// Maps the time reported by the participant to the server clock, using
+    /// the instant the round start marker was sent and the estimated one way
+    /// latency. Only buzzes received during the settling window by
+    /// participants with a known round trip time are considered.
+    fn reported_time(
+        &self,
+        round: Ulid,
+        elapsed: Duration,
+        rtt: Option<Duration>,
+        received: Instant,
+    ) -> Option<Instant> {
+        if self.config.settling_window.is_zero()
+            || self
+                .run
+                .first_received()
+                .is_some_and(|first| received > first + self.config.settling_window)
+        {
             return None;
         }
-        self.selection += 1;
-        Some((
-            self.buzzed[self.selection - 1].0,
-            self.buzzed[self.selection].0,
-        ))
+        let one_way = (rtt? / 2).min(MAX_LATENCY_COMPENSATION);
+        reported_time(self.run.round_start(round)?, elapsed, one_way, received)
     }
 
-    fn first_unchecked(&self) -> Ulid {
-        assert_eq!(self.buzzed.len(), 1);
-        self.buzzed[0].0
+    async fn select_next(&mut self) {
+        if self.settling.is_some() {
+            return;
+        }
+        let Some((to_clear, to_notify)) = self.run.select_next() else {
+            return;
+        };
+        self.send_single(to_clear, PacketOut::Deselect);
+        self.send_single(to_notify, PacketOut::Select { id: None });
+        self.send_host(PacketOut::Select {
+            id: Some(to_notify),
+        })
+        .await;
     }
-}
 
-enum BuzzResult {
-    Already,
-    First,
-    TimeDifference(u64),
-}
+    async fn clear(&mut self) {
+        if let Some(timer) = self.timer.take() {
+            timer.ticker.abort();
+        }
+        if let Some((_, task)) = self.settling.take() {
+            task.abort();
+        }
+        self.run = Run::new(self.run.compensated);
+        self.send_everyone(PacketOut::Clear).await;
+        let round = self.run.mark_round_start();
+        self.send_all(PacketOut::RoundStart { id: round });
+    }
 
-struct Participant {
-    id: Ulid,
-    name: Box<str>,
-}
+    async fn set_armed(&mut self, armed: bool) {
+        if self.armed == armed {
+            return;
+        }
+        self.armed = armed;
+        self.send_everyone(if armed {
+            PacketOut::Armed
+        } else {
+            PacketOut::Locked
+        })
+        .await;
+        if armed {
+            let round = self.run.mark_round_start();
+            self.send_all(PacketOut::RoundStart { id: round });
+        }
+    }
 
-#[derive(Clone)]
-enum RoomMessage {
-    ParticipantJoin,
-    Buzzed(Arc<Participant>, Instant),
-    SelectNext,
-    Clear,
-    ParticipantLeft,
-    HostLeft,
-}
+    /// Starts a countdown for the current run, arming the buzzer if needed.
+    async fn start_timer(&mut self, from: Ulid, seconds: u64) {
+        let start = Instant::now();
+        let Some(deadline) = Some(Duration::from_secs(seconds))
+            .filter(|duration| *duration <= MAX_TIMER_DURATION)
+            .and_then(|duration| start.checked_add(duration))
+        else {
+            self.reject(
+                from,
+                ErrorCode::InvalidValue,
+                format!(
+                    "the timer can't last more than {} seconds",
+                    MAX_TIMER_DURATION.as_secs()
+                )
+                .into(),
+            )
+            .await;
+            return;
+        };
+        if let Some(timer) = self.timer.take() {
+            timer.ticker.abort();
+        }
 
-#[derive(Clone, Debug)]
-enum BroadcastMessage {
-    All(WsMessage),
-    Single(Ulid, Arc<WsMessage>),
-}
+        let id = Ulid::new();
+        let main_tx = self.main.clone();
+        let ticker = tokio::spawn(async move {
+            let mut interval = time::interval_at(time::Instant::from_std(start), TIMER_TICK);
+            loop {
+                interval.tick().await;
+                if main_tx.send(RoomMessage::TimerTick(id)).await.is_err() {
+                    return;
+                }
+            }
+        });
+        self.run.deadline = Some(deadline);
+        self.timer = Some(Timer {
+            id,
+            deadline,
+            ticker,
+        });
+        self.set_armed(true).await;
+    }
 
-impl BroadcastMessage {
-    fn is_target(&self, id: &Ulid) -> bool {
-        match self {
-            BroadcastMessage::All(_) => true,
-            BroadcastMessage::Single(target_id, _) => target_id == id,
+    async fn stop_timer(&mut self) {
+        let Some(timer) = self.timer.take() else {
+            return;
+        };
+        timer.ticker.abort();
+        self.run.deadline = None;
+        self.send_everyone(PacketOut::TimerStopped).await;
+    }
+
+    async fn timer_tick(&mut self, timer_id: Ulid) {
+        let Some(timer) = self.timer.as_ref().filter(|t| t.id == timer_id) else {
+            return;
+        };
+        let remaining = timer.remaining();
+        if remaining > 0 {
+            self.send_everyone(PacketOut::Timer { remaining }).await;
+            return;
+        }
+
+        if let Some(timer) = self.timer.take() {
+            timer.ticker.abort();
         }
+        self.send_everyone(PacketOut::TimerExpired).await;
+        self.set_armed(false).await;
     }
 
-    fn inner(self) -> WsMessage {
-        match self {
-            BroadcastMessage::All(msg) => msg,
-            BroadcastMessage::Single(_, msg) => (*msg).clone(),
+    async fn judge(&mut self, verdict: Verdict) {
+        if self.settling.is_some() {
+            return;
+        }
+        let Some(participant_id) = self.run.judge_selected(verdict) else {
+            return;
+        };
+        self.scoreboard.judge(participant_id, verdict);
+        self.send_host(PacketOut::Judged {
+            id: participant_id,
+            verdict,
+        })
+        .await;
+        match verdict {
+            Verdict::Correct => {
+                self.send_all(PacketOut::Answered);
+                self.send_single(participant_id, PacketOut::Correct);
+                self.stop_timer().await;
+            }
+            Verdict::Incorrect => {
+                self.send_single(participant_id, PacketOut::Incorrect);
+                if let Some(next) = self.run.advance() {
+                    self.send_single(next, PacketOut::Select { id: None });
+                    self.send_host(PacketOut::Select { id: Some(next) }).await;
+                }
+            }
         }
+        self.send_scoreboard().await;
+    }
+
+    async fn create_team(&mut self, name: Box<str>) {
+        if self.teams.create(name).is_some() {
+            self.send_teams().await;
+        }
+    }
+
+    async fn rename_team(&mut self, team_id: Ulid, name: Box<str>) {
+        if !self.teams.rename(team_id, name) {
+            return;
+        }
+        for member in self.teams.members(team_id) {
+            self.send_single(
+                member,
+                PacketOut::Team {
+                    name: self.teams.name_of(member),
+                },
+            );
+        }
+        self.send_teams().await;
+    }
+
+    async fn assign_team(&mut self, participant_id: Ulid, team_id: Option<Ulid>) {
+        if !self.participants.contains(participant_id)
+            || !self.teams.assign(participant_id, team_id)
+        {
+            return;
+        }
+        self.send_single(
+            participant_id,
+            PacketOut::Team {
+                name: self.teams.name_of(participant_id),
+            },
+        );
+        self.send_teams().await;
+    }
+
+    async fn rename_participant(&mut self, participant_id: Ulid, name: Box<str>) {
+        let Ok(name) = sanitize_username(&name) else {
+            return;
+        };
+        let Ok(participant) = self.participants.rename(participant_id, name) else {
+            return;
+        };
+        self.send_host(PacketOut::ParticipantRenamed {
+            id: participant.id,
+            name: participant.name.clone(),
+        })
+        .await;
+        self.send_scoreboard().await;
+    }
+
+    async fn send_teams(&mut self) {
+        self.send_host(self.teams.packet()).await;
+    }
+
+    async fn send_scoreboard(&mut self) {
+        self.send_everyone(self.scoreboard.packet(&self.participants))
+            .await;
+    }
+
+    async fn kick(&mut self, participant_id: Ulid, reason: Option<Box<str>>, banned: bool) {
+        if !self.participants.kick(participant_id) {
+            return;
+        }
+        _ = self.broadcast.send(BroadcastMessage::Kick(
+            participant_id,
+            Arc::new(WsMessage::from(PacketOut::Kicked { reason, banned })),
+        ));
+        self.participant_disconnected(participant_id).await;
+        info!(id = as_display!(self.id), room = as_display!(self.name), participant = as_display!(participant_id); "participant kicked");
+    }
+
+    /// Bans the session of the participant, as well as its last IP address if
+    /// requested, then kicks them.
+    async fn ban(&mut self, participant_id: Ulid, reason: Option<Box<str>>, ip: bool) {
+        let Some((session, address)) = self.participants.session_of(participant_id) else {
+            return;
+        };
+        _ = self
+            .registry
+            .upgrade()
+            .expect("registry deallocated")
+            .lock()
+            .await
+            .ban(self.id, session, ip.then_some(address));
+        self.kick(participant_id, reason, true).await;
+    }
+
+    async fn participant_left(&mut self, participant_id: Ulid, connection: Ulid) {
+        if self.participants.disconnect(participant_id, connection) {
+            self.participant_disconnected(participant_id).await;
+        }
+    }
+
+    async fn participant_disconnected(&mut self, participant_id: Ulid) {
+        self.send_host(PacketOut::ParticipantLeft { id: participant_id })
+            .await;
+        self.send_everyone(PacketOut::ParticipantCount {
+            count: self.participants.count(),
+        })
+        .await;
+        self.send_scoreboard().await;
+    }
+
+    /// Adds a host connection, either a reconnecting host or a new co-host.
+    /// Previous connections are kept, as they may belong to other hosts.
+    async fn host_connect(&mut self, socket: WebSocket, role: HostRole) {
+        let was_disconnected = self.hosts.is_empty();
+        let mut host = HostConnection::new(
+            socket,
+            role,
+            self.config.heartbeat_deadline(),
+            self.main.clone(),
+        );
+        for packet in [
+            self.host_state(),
+            self.scoreboard.packet(&self.participants),
+            self.teams.packet(),
+            self.participants.roster(),
+        ] {
+            host.send(WsMessage::from(packet)).await;
+        }
+        self.hosts.insert(host.id, host);
+        self.send_hosts().await;
+
+        if was_disconnected {
+            self.send_all(PacketOut::HostReconnected);
+        }
+        info!(id = as_display!(self.id), room = as_display!(self.name), role = as_debug!(role); "host connected");
+    }
+
+    /// The room stays open as long as a host is connected, or during the grace
+    /// period following the departure of the last one.
+    async fn host_left(&mut self, connection_id: Ulid) -> ControlFlow<()> {
+        if self.hosts.remove(&connection_id).is_none() {
+            return ControlFlow::Continue(());
+        }
+        if !self.hosts.is_empty() {
+            self.send_hosts().await;
+            info!(id = as_display!(self.id), room = as_display!(self.name); "co-host disconnected");
+            return ControlFlow::Continue(());
+        }
+        self.last_host_left = connection_id;
+        if self.config.host_grace_period.is_zero() {
+            return ControlFlow::Break(());
+        }
+
+        self.send_all(PacketOut::HostDisconnected);
+        let main_tx = self.main.clone();
+        let grace_period = self.config.host_grace_period;
+        tokio::spawn(async move {
+            time::sleep(grace_period).await;
+            _ = main_tx
+                .send(RoomMessage::HostGracePeriodExpired(connection_id))
+                .await;
+        });
+        info!(id = as_display!(self.id), room = as_display!(self.name); "host disconnected");
+        ControlFlow::Continue(())
+    }
+
+    /// Returns the packet announcing the closing of the room, if requested by
+    /// its owner.
+    fn close_room(&self, from: Ulid, reason: Option<Box<str>>) -> Option<PacketOut> {
+        if !matches!(self.hosts.get(&from).map(|h| h.role), Some(HostRole::Owner)) {
+            return None;
+        }
+        info!(id = as_display!(self.id), room = as_display!(self.name); "room closed by the owner");
+        Some(PacketOut::RoomClosed {
+            reason: reason
+                .map(|r| utils::sanitize(&r))
+                .filter(|r| !r.is_empty()),
+            summary: Summary {
+                duration: self.created.elapsed().as_secs(),
+                scores: self.scoreboard.scores(&self.participants),
+            },
+        })
+    }
+
+    /// Gives the ownership of the room to a co-host, or to a participant who
+    /// then connects as the owner. Previous owner connections become co-hosts,
+    /// once the promoted participant is connected.
+    async fn transfer_ownership(&mut self, from: Ulid, to: Ulid) {
+        if !matches!(self.hosts.get(&from).map(|h| h.role), Some(HostRole::Owner)) {
+            self.reject(
+                from,
+                ErrorCode::Unauthorized,
+                "only the owner can hand the room over".into(),
+            )
+            .await;
+            return;
+        }
+        if matches!(self.hosts.get(&to).map(|h| h.role), Some(HostRole::Owner)) {
+            return;
+        }
+        let registry = self.registry.upgrade().expect("registry deallocated");
+        let secret = utils::generate_token();
+
+        if !self.hosts.contains_key(&to) {
+            let participant = match self.participants.get(to) {
+                Some(participant) if self.participants.is_connected(to) => participant,
+                _ => return,
+            };
+            // The owner keeps the room until the participant connects as a host.
+            if registry
+                .lock()
+                .await
+                .promote(self.id, secret.clone())
+                .is_err()
+            {
+                return;
+            }
+            self.send_single(participant.id, PacketOut::Promoted { secret });
+            self.promoted = Some(participant);
+            info!(id = as_display!(self.id), room = as_display!(self.name); "participant promoted");
+            return;
+        }
+
+        let Ok(cohost_secret) = registry
+            .lock()
+            .await
+            .transfer_ownership(self.id, secret.clone())
+        else {
+            return;
+        };
+        self.promoted = None;
+        self.demote_owners(&cohost_secret).await;
+        if let Some(host) = self.hosts.get_mut(&to) {
+            host.role = HostRole::Owner;
+            host.send(WsMessage::from(PacketOut::HostRole {
+                owner: true,
+                secret,
+            }))
+            .await;
+        }
+        self.send_everyone(PacketOut::OwnershipTransferred { name: None })
+            .await;
+        self.send_hosts().await;
+        info!(id = as_display!(self.id), room = as_display!(self.name); "ownership transferred");
+    }
+
+    /// Completes the transfer of the ownership to a promoted participant, now
+    /// connected as a host.
+    async fn promoted_connect(&mut self, socket: WebSocket, cohost_secret: Box<str>) {
+        self.demote_owners(&cohost_secret).await;
+        self.host_connect(socket, HostRole::Owner).await;
+        let name = self.promoted.take().map(|p| p.name.clone());
+        self.send_everyone(PacketOut::OwnershipTransferred { name })
+            .await;
+        info!(id = as_display!(self.id), room = as_display!(self.name); "ownership transferred");
+    }
+
+    /// Turns the owner connections into co-host ones, giving them the co-host
+    /// secret.
+    async fn demote_owners(&mut self, cohost_secret: &str) {
+        for host in self
+            .hosts
+            .values_mut()
+            .filter(|h| matches!(h.role, HostRole::Owner))
+        {
+            host.role = HostRole::CoHost;
+            host.send(WsMessage::from(PacketOut::HostRole {
+                owner: false,
+                secret: cohost_secret.into(),
+            }))
+            .await;
+        }
+    }
+
+    /// Sends the list of host connections to every host, along with the id of
+    /// their own connection.
+    async fn send_hosts(&mut self) {
+        let mut hosts = self
+            .hosts
+            .values()
+            .map(|h| HostEntry {
+                id: h.id,
+                owner: matches!(h.role, HostRole::Owner),
+            })
+            .collect::<Vec<_>>();
+        hosts.sort_by_key(|h| h.id);
+        for host in self.hosts.values_mut() {
+            host.send(WsMessage::from(PacketOut::Hosts {
+                id: host.id,
+                hosts: hosts.clone(),
+            }))
+            .await;
+        }
+    }
+
+    fn host_state(&self) -> PacketOut {
+        PacketOut::HostState {
+            participant_count: self.participants.count(),
+            armed: self.armed,
+            timer: self.timer.as_ref().map(Timer::remaining),
+            buzzed: self.run.snapshot(&self.teams),
+            selected: self.run.selected(),
+            latency_compensation: self.run.compensated,
+        }
+    }
+
+    /// Sends the packet to every host and to every spectator.
+    async fn send_host(&mut self, packet: PacketOut) {
+        let packet = WsMessage::from(packet);
+        for host in self.hosts.values_mut() {
+            host.send(packet.clone()).await;
+        }
+        _ = self.broadcast.send(BroadcastMessage::Spectators(packet));
+    }
+
+    fn send_all(&self, packet: PacketOut) {
+        _ = self
+            .broadcast
+            .send(BroadcastMessage::All(WsMessage::from(packet)));
+    }
+
+    fn send_single(&self, participant: Ulid, packet: PacketOut) {
+        _ = self.broadcast.send(BroadcastMessage::Single(
+            participant,
+            Arc::new(WsMessage::from(packet)),
+        ));
+    }
+
+    /// Answers an invalid packet, `to` being either a host connection or a
+    /// participant.
+    async fn reject(&mut self, to: Ulid, code: ErrorCode, message: Box<str>) {
+        let packet = PacketOut::Error { code, message };
+        match self.hosts.get_mut(&to) {
+            Some(host) => host.send(WsMessage::from(packet)).await,
+            None => self.send_single(to, packet),
+        }
+    }
+
+    /// Sends the packet to every host and to every participant.
+    async fn send_everyone(&mut self, packet: PacketOut) {
+        let packet = WsMessage::from(packet);
+        for host in self.hosts.values_mut() {
+            host.send(packet.clone()).await;
+        }
+        _ = self.broadcast.send(BroadcastMessage::All(packet));
+    }
+}
+
+struct Timer {
+    id: Ulid,
+    deadline: Instant,
+    ticker: JoinHandle<()>,
+}
+
+impl Timer {
+    /// Remaining seconds, rounded up.
+    fn remaining(&self) -> u64 {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        remaining.as_millis().div_ceil(1000) as u64
+    }
+}
+
+/// The owner is the host who created the room, co-hosts joined later using
+/// the co-host secret.
+#[derive(Clone, Copy, Debug)]
+pub enum HostRole {
+    Owner,
+    CoHost,
+}
+
+struct HostConnection {
+    id: Ulid,
+    role: HostRole,
+    tx: SplitSink<WebSocket, WsMessage>,
+    reader: JoinHandle<()>,
+}
+
+impl HostConnection {
+    fn new(
+        socket: WebSocket,
+        role: HostRole,
+        deadline: Duration,
+        main_tx: MpscSender<RoomMessage>,
+    ) -> Self {
+        let id = Ulid::new();
+        let (tx, mut rx) = socket.split();
+
+        let reader = tokio::spawn(async move {
+            let mut errors = PacketErrors::new();
+            loop {
+                let message = match next_message(&mut rx, deadline).await {
+                    Some(WsMessage::Ping(_) | WsMessage::Pong(_)) => continue,
+                    Some(msg) => match PacketIn::try_from(msg)
+                        .map(|packet| RoomMessage::from_host_packet(packet, id))
+                    {
+                        Ok(Some(message)) => message,
+                        Ok(None) | Err(_) if errors.record() => break,
+                        Ok(None) => RoomMessage::Rejected {
+                            to: id,
+                            code: ErrorCode::UnexpectedPacket,
+                            message: "this packet isn't expected from a host".into(),
+                        },
+                        Err(message) => RoomMessage::Rejected {
+                            to: id,
+                            code: ErrorCode::MalformedPacket,
+                            message,
+                        },
+                    },
+                    None => break,
+                };
+                main_tx.send(message).await.expect("send failed");
+            }
+            _ = main_tx.send(RoomMessage::HostLeft(id)).await;
+        });
+
+        Self {
+            id,
+            role,
+            tx,
+            reader,
+        }
+    }
+
+    async fn send(&mut self, msg: WsMessage) {
+        // A failing host socket is detected by its reader, which will then report it
+        // as left.
+        _ = self.tx.send(msg).await;
+    }
+
+    async fn close(mut self) {
+        self.reader.abort();
+        _ = self.tx.close().await;
+    }
+}
+
+#[derive(Debug)]
+struct Run {
+    buzzed: Vec<RunBuzz>,
+    selection: usize,
+    deadline: Option<Instant>,
+    // Whether buzzes are ordered by their arrival time minus half the round
+    // trip time of the participant.
+    compensated: bool,
+    // Instants at which the round start markers were sent.
+    round_starts: HashMap<Ulid, Instant>,
+}
+
+#[derive(Debug)]
+struct RunBuzz {
+    participant: Arc<Participant>,
+    team: Option<Ulid>,
+    time: Instant,
+    rtt: Option<Duration>,
+    // Time reported by the participant, mapped to the server clock.
+    reported: Option<Instant>,
+    // Whether the buzz happened before the countdown deadline, if any.
+    in_time: Option<bool>,
+    verdict: Option<Verdict>,
+}
+
+impl RunBuzz {
+    /// Reported times take precedence over the compensated arrival time.
+    fn effective_time(&self, compensated: bool) -> Instant {
+        if let Some(reported) = self.reported {
+            return reported;
+        }
+        match self.rtt.filter(|_| compensated) {
+            Some(rtt) => self
+                .time
+                .checked_sub((rtt / 2).min(MAX_LATENCY_COMPENSATION))
+                .unwrap_or(self.time),
+            None => self.time,
+        }
+    }
+}
+
+impl Run {
+    fn new(compensated: bool) -> Self {
+        Self {
+            buzzed: Vec::new(),
+            selection: 0,
+            deadline: None,
+            compensated,
+            round_starts: HashMap::new(),
+        }
+    }
+
+    fn mark_round_start(&mut self) -> Ulid {
+        let id = Ulid::new();
+        self.round_starts.insert(id, Instant::now());
+        id
+    }
+
+    fn round_start(&self, id: Ulid) -> Option<Instant> {
+        self.round_starts.get(&id).copied()
+    }
+
+    fn first_received(&self) -> Option<Instant> {
+        self.buzzed.iter().map(|b| b.time).min()
+    }
+
+    /// Only the first buzz of a team is recorded. When compensating latency,
+    /// the buzz may overtake the selected buzz if it wasn't judged yet, as
+    /// well as the ones waiting after it.
+    fn buzz(
+        &mut self,
+        buzzer: Arc<Participant>,
+        team: Option<Ulid>,
+        time: Instant,
+        rtt: Option<Duration>,
+        reported: Option<Instant>,
+    ) -> BuzzResult {
+        // Start from the back because it's likely the last participant spamming the
+        // buzzer.
+        if self
+            .buzzed
+            .iter()
+            .rev()
+            .any(|b| b.participant.id == buzzer.id || (team.is_some() && b.team == team))
+        {
+            return BuzzResult::Already;
+        }
+        let buzz = RunBuzz {
+            participant: buzzer,
+            team,
+            time,
+            rtt,
+            reported,
+            in_time: self.deadline.map(|deadline| time <= deadline),
+            verdict: None,
+        };
+        let first_reorderable = match self.buzzed.get(self.selection) {
+            Some(selected) if selected.verdict.is_none() => self.selection,
+            _ => (self.selection + 1).min(self.buzzed.len()),
+        };
+        let effective_time = buzz.effective_time(self.compensated);
+        let position = (first_reorderable..self.buzzed.len())
+            .find(|&i| self.buzzed[i].effective_time(self.compensated) > effective_time)
+            .unwrap_or(self.buzzed.len());
+        self.buzzed.insert(position, buzz);
+
+        if self.buzzed.len() == 1 {
+            BuzzResult::First
+        } else if position == self.buzzed.len() - 1 {
+            BuzzResult::TimeDifference(self.time_difference(position))
+        } else {
+            BuzzResult::Overtook(
+                (position == self.selection).then(|| self.buzzed[position + 1].participant.id),
+            )
+        }
+    }
+
+    /// Reorders the buzzes waiting after the selected one.
+    fn set_compensated(&mut self, compensated: bool) {
+        self.compensated = compensated;
+        let first_reorderable = (self.selection + 1).min(self.buzzed.len());
+        self.buzzed[first_reorderable..].sort_by_key(|b| b.effective_time(compensated));
+    }
+
+    /// Milliseconds between the first buzz and the given one.
+    fn time_difference(&self, index: usize) -> u64 {
+        self.buzzed[index]
+            .effective_time(self.compensated)
+            .saturating_duration_since(self.buzzed[0].effective_time(self.compensated))
+            .as_millis() as u64
+    }
+
+    fn select_next(&mut self) -> Option<(Ulid, Ulid)> {
+        if self.selection + 1 >= self.buzzed.len() || self.winner().is_some() {
+            return None;
+        }
+        self.selection += 1;
+        Some((
+            self.buzzed[self.selection - 1].participant.id,
+            self.buzzed[self.selection].participant.id,
+        ))
+    }
+
+    /// Records the verdict for the selected buzz, unless it was already judged.
+    fn judge_selected(&mut self, verdict: Verdict) -> Option<Ulid> {
+        let buzz = self.buzzed.get_mut(self.selection)?;
+        if buzz.verdict.is_some() {
+            return None;
+        }
+        buzz.verdict = Some(verdict);
+        Some(buzz.participant.id)
+    }
+
+    /// Moves the selection past the judged buzz. The selection may point past
+    /// the end of the list, in which case the next buzzer gets selected.
+    fn advance(&mut self) -> Option<Ulid> {
+        self.selection += 1;
+        self.selected()
+    }
+
+    fn winner(&self) -> Option<Ulid> {
+        self.buzzed
+            .iter()
+            .find(|b| matches!(b.verdict, Some(Verdict::Correct)))
+            .map(|b| b.participant.id)
+    }
+
+    fn verdict(&self, participant: Ulid) -> Option<Verdict> {
+        self.buzzed
+            .iter()
+            .find(|b| b.participant.id == participant)
+            .and_then(|b| b.verdict)
+    }
+
+    fn last_in_time(&self) -> Option<bool> {
+        self.buzzed.last().and_then(|b| b.in_time)
+    }
+
+    fn has_buzzed(&self, participant: Ulid) -> bool {
+        self.buzzed.iter().any(|b| b.participant.id == participant)
+    }
+
+    fn is_waiting(&self, participant: Ulid) -> bool {
+        self.buzzed
+            .iter()
+            .skip(self.selection + 1)
+            .any(|b| b.participant.id == participant)
+    }
+
+    fn selected(&self) -> Option<Ulid> {
+        self.buzzed.get(self.selection).map(|b| b.participant.id)
+    }
+
+    fn snapshot(&self, teams: &Teams) -> Vec<Buzz> {
+        self.buzzed
+            .iter()
+            .enumerate()
+            .map(|(i, buzz)| Buzz {
+                id: buzz.participant.id,
+                name: buzz.participant.name.clone(),
+                team: teams.name_of(buzz.participant.id),
+                timestamp_diff: (i != 0).then(|| self.time_difference(i)),
+                in_time: buzz.in_time,
+                verdict: buzz.verdict,
+                rtt: buzz.rtt.map(|rtt| rtt.as_millis() as u64),
+            })
+            .collect()
+    }
+}
+
+enum BuzzResult {
+    Already,
+    First,
+    TimeDifference(u64),
+    // Inserted before other buzzes, along with the participant who was
+    // selected, if overtaken.
+    Overtook(Option<Ulid>),
+}
+
+#[derive(Debug)]
+struct Participant {
+    id: Ulid,
+    name: Box<str>,
+}
+
+struct Participants {
+    sessions: HashMap<Ulid, ParticipantSession>,
+    tokens: HashMap<Box<str>, Ulid>,
+    // Shared with the room to be listed in the directory.
+    connected: Arc<AtomicUsize>,
+    usernames: Arc<RwLock<Usernames>>,
+}
+
+struct ParticipantSession {
+    participant: Arc<Participant>,
+    token: Box<str>,
+    // Address of the last connection.
+    ip: IpAddr,
+    connection: Option<Ulid>,
+    // Smoothed round trip time of the current connection.
+    rtt: Option<Duration>,
+}
+
+impl Participants {
+    fn new(usernames: Arc<RwLock<Usernames>>, connected: Arc<AtomicUsize>) -> Self {
+        Self {
+            sessions: HashMap::new(),
+            tokens: HashMap::new(),
+            connected,
+            usernames,
+        }
+    }
+
+    /// Attaches a connection to the session matching the token, or to a new
+    /// session if the token is missing or unknown. Also returns the session
+    /// token and whether the participant wasn't connected yet.
+    fn connect(
+        &mut self,
+        name: Box<str>,
+        token: Option<&str>,
+        ip: IpAddr,
+        connection: Ulid,
+    ) -> Result<(Arc<Participant>, Box<str>, bool), Error> {
+        let id = match token.and_then(|t| self.tokens.get(t)) {
+            Some(id) => {
+                // The name may have been taken by someone else while the
+                // participant was away.
+                let session = &self.sessions[id];
+                self.usernames
+                    .read()
+                    .expect("usernames lock poisoned")
+                    .check(&session.participant.name, Some(&session.token))?;
+                *id
+            }
+            None => {
+                self.usernames
+                    .read()
+                    .expect("usernames lock poisoned")
+                    .check(&name, None)?;
+                let id = Ulid::new();
+                let token = utils::generate_token();
+                self.tokens.insert(token.clone(), id);
+                self.sessions.insert(
+                    id,
+                    ParticipantSession {
+                        participant: Arc::new(Participant { id, name }),
+                        token,
+                        ip,
+                        connection: None,
+                        rtt: None,
+                    },
+                );
+                id
+            }
+        };
+
+        let session = self.sessions.get_mut(&id).expect("missing session");
+        session.ip = ip;
+        let newly_connected = session.connection.replace(connection).is_none();
+        if newly_connected {
+            session.rtt = None;
+            self.connected.fetch_add(1, Ordering::Relaxed);
+            self.usernames
+                .write()
+                .expect("usernames lock poisoned")
+                .insert(&session.participant.name, &session.token);
+        }
+        Ok((
+            Arc::clone(&session.participant),
+            session.token.clone(),
+            newly_connected,
+        ))
+    }
+
+    /// Returns false if the connection was already replaced by a newer one.
+    fn disconnect(&mut self, id: Ulid, connection: Ulid) -> bool {
+        let Some(session) = self.sessions.get_mut(&id) else {
+            return false;
+        };
+        if session.connection != Some(connection) {
+            return false;
+        }
+        session.connection = None;
+        self.connected.fetch_sub(1, Ordering::Relaxed);
+        self.usernames
+            .write()
+            .expect("usernames lock poisoned")
+            .remove(&session.participant.name, &session.token);
+        true
+    }
+
+    /// Drops the current connection of the participant, if any.
+    fn kick(&mut self, id: Ulid) -> bool {
+        let Some(session) = self.sessions.get_mut(&id) else {
+            return false;
+        };
+        if session.connection.take().is_none() {
+            return false;
+        }
+        self.connected.fetch_sub(1, Ordering::Relaxed);
+        self.usernames
+            .write()
+            .expect("usernames lock poisoned")
+            .remove(&session.participant.name, &session.token);
+        true
+    }
+
+    fn count(&self) -> usize {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    fn is_connected(&self, id: Ulid) -> bool {
+        self.sessions
+            .get(&id)
+            .is_some_and(|s| s.connection.is_some())
+    }
+
+    fn record_rtt(&mut self, id: Ulid, sample: Duration) {
+        if let Some(session) = self.sessions.get_mut(&id) {
+            session.rtt = Some(match session.rtt {
+                Some(rtt) => (rtt * 3 + sample) / 4,
+                None => sample,
+            });
+        }
+    }
+
+    fn rtt(&self, id: Ulid) -> Option<Duration> {
+        self.sessions.get(&id).and_then(|s| s.rtt)
+    }
+
+    fn session_of(&self, id: Ulid) -> Option<(Box<str>, IpAddr)> {
+        self.sessions.get(&id).map(|s| (s.token.clone(), s.ip))
+    }
+
+    fn contains(&self, id: Ulid) -> bool {
+        self.sessions.contains_key(&id)
+    }
+
+    fn get(&self, id: Ulid) -> Option<Arc<Participant>> {
+        self.sessions.get(&id).map(|s| Arc::clone(&s.participant))
+    }
+
+    fn rename(&mut self, id: Ulid, name: Box<str>) -> Result<Arc<Participant>, Error> {
+        let session = self
+            .sessions
+            .get_mut(&id)
+            .ok_or(Error::ParticipantNotFound)?;
+        if session.connection.is_some() {
+            let mut usernames = self.usernames.write().expect("usernames lock poisoned");
+            usernames.check(&name, Some(&session.token))?;
+            usernames.remove(&session.participant.name, &session.token);
+            usernames.insert(&name, &session.token);
+        }
+        session.participant = Arc::new(Participant { id, name });
+        Ok(Arc::clone(&session.participant))
+    }
+
+    /// Snapshot of the connected participants, in joining order.
+    fn roster(&self) -> PacketOut {
+        let mut participants = self
+            .iter()
+            .filter(|(_, connected)| *connected)
+            .map(|(p, _)| RosterEntry {
+                id: p.id,
+                name: p.name.clone(),
+                rtt: self.rtt(p.id).map(|rtt| rtt.as_millis() as u64),
+            })
+            .collect::<Vec<_>>();
+        participants.sort_by_key(|p| p.id);
+        PacketOut::Roster { participants }
+    }
+
+    /// Iterates over every participant who ever joined, along with their
+    /// connection status.
+    fn iter(&self) -> impl Iterator<Item = (&Arc<Participant>, bool)> {
+        self.sessions
+            .values()
+            .map(|s| (&s.participant, s.connection.is_some()))
+    }
+}
+
+/// Normalized names of the connected participants, mapped to their session
+/// token. Shared with the registry to reject duplicates before upgrading
+/// connections.
+#[derive(Debug)]
+pub struct Usernames(HashMap<Box<str>, Box<str>>);
+
+impl Usernames {
+    fn new() -> Self {
+        Self(HashMap::new())
+    }
+
+    /// Fails if the name is used by another session than the given one.
+    pub fn check(&self, name: &str, session: Option<&str>) -> Result<(), Error> {
+        match self.0.get(&utils::sanitize_for_search(name)) {
+            Some(token) if Some(&**token) != session => Err(Error::UsernameTaken {
+                suggestion: self.suggest(name),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn suggest(&self, name: &str) -> Box<str> {
+        let name = utils::sanitize(name);
+        (2..)
+            .map(|n| {
+                let suffix = format!(" {n}");
+                let name = name
+                    .chars()
+                    .take(USERNAME_MAX_LEN - suffix.len())
+                    .collect::<String>();
+                format!("{}{suffix}", name.trim_end())
+            })
+            .find(|candidate| !self.0.contains_key(&utils::sanitize_for_search(candidate)))
+            .expect("no username available")
+            .into_boxed_str()
+    }
+
+    /// Keeps the current owner if the name is already used, which may happen
+    /// when a session is resumed.
+    fn insert(&mut self, name: &str, session: &str) {
+        self.0
+            .entry(utils::sanitize_for_search(name))
+            .or_insert_with(|| session.into());
+    }
+
+    fn remove(&mut self, name: &str, session: &str) {
+        let name = utils::sanitize_for_search(name);
+        if self.0.get(&name).is_some_and(|token| **token == *session) {
+            self.0.remove(&name);
+        }
+    }
+}
+
+struct Teams {
+    // Kept in creation order.
+    teams: Vec<Team>,
+    memberships: HashMap<Ulid, Ulid>,
+}
+
+struct Team {
+    id: Ulid,
+    name: Box<str>,
+}
+
+impl Teams {
+    fn new() -> Self {
+        Self {
+            teams: Vec::new(),
+            memberships: HashMap::new(),
+        }
+    }
+
+    /// Returns `None` if the name is invalid or already used by another team.
+    fn create(&mut self, name: Box<str>) -> Option<Ulid> {
+        let name = Self::sanitize(&name)?;
+        if self.find(&name).is_some() {
+            return None;
+        }
+        let id = Ulid::new();
+        self.teams.push(Team { id, name });
+        Some(id)
+    }
+
+    fn find_or_create(&mut self, name: Box<str>) -> Option<Ulid> {
+        match self.find(&name) {
+            Some(id) => Some(id),
+            None => self.create(name),
+        }
+    }
+
+    fn sanitize(name: &str) -> Option<Box<str>> {
+        let name = utils::sanitize(name);
+        (!name.is_empty() && name.chars().count() <= TEAM_NAME_MAX_LEN).then_some(name)
+    }
+
+    fn find(&self, name: &str) -> Option<Ulid> {
+        let name = utils::sanitize_for_search(name);
+        self.teams
+            .iter()
+            .find(|t| utils::sanitize_for_search(&t.name) == name)
+            .map(|t| t.id)
+    }
+
+    fn rename(&mut self, id: Ulid, name: Box<str>) -> bool {
+        let Some(name) = Self::sanitize(&name) else {
+            return false;
+        };
+        if self.find(&name).is_some_and(|other| other != id) {
+            return false;
+        }
+        let Some(team) = self.teams.iter_mut().find(|t| t.id == id) else {
+            return false;
+        };
+        team.name = name;
+        true
+    }
+
+    /// Moves the participant to the team, or out of any team if `None`.
+    /// Returns false if the team doesn't exist or nothing changed.
+    fn assign(&mut self, participant: Ulid, team: Option<Ulid>) -> bool {
+        match team {
+            Some(team) if self.teams.iter().all(|t| t.id != team) => false,
+            Some(team) => self.memberships.insert(participant, team) != Some(team),
+            None => self.memberships.remove(&participant).is_some(),
+        }
+    }
+
+    fn team_of(&self, participant: Ulid) -> Option<Ulid> {
+        self.memberships.get(&participant).copied()
+    }
+
+    fn name_of(&self, participant: Ulid) -> Option<Box<str>> {
+        let team = self.team_of(participant)?;
+        self.teams
+            .iter()
+            .find(|t| t.id == team)
+            .map(|t| t.name.clone())
+    }
+
+    fn members(&self, team: Ulid) -> Vec<Ulid> {
+        self.memberships
+            .iter()
+            .filter(|(_, t)| **t == team)
+            .map(|(p, _)| *p)
+            .collect()
+    }
+
+    fn packet(&self) -> PacketOut {
+        PacketOut::Teams {
+            teams: self
+                .teams
+                .iter()
+                .map(|t| TeamInfo {
+                    id: t.id,
+                    name: t.name.clone(),
+                    members: self.members(t.id),
+                })
+                .collect(),
+        }
+    }
+}
+
+struct Scoreboard {
+    scores: HashMap<Ulid, i64>,
+    correct_points: i64,
+    incorrect_points: i64,
+}
+
+impl Scoreboard {
+    fn new() -> Self {
+        Self {
+            scores: HashMap::new(),
+            correct_points: 1,
+            incorrect_points: 0,
+        }
+    }
+
+    fn judge(&mut self, participant: Ulid, verdict: Verdict) {
+        let points = match verdict {
+            Verdict::Correct => self.correct_points,
+            Verdict::Incorrect => self.incorrect_points,
+        };
+        self.adjust(participant, points);
+    }
+
+    fn adjust(&mut self, participant: Ulid, delta: i64) {
+        let score = self.scores.entry(participant).or_default();
+        *score = score.saturating_add(delta);
+    }
+
+    fn reset(&mut self) {
+        self.scores.clear();
+    }
+
+    /// Lists connected participants and the ones who left with a score, highest
+    /// score first.
+    fn scores(&self, participants: &Participants) -> Vec<Score> {
+        let mut scores = participants
+            .iter()
+            .filter(|(p, connected)| *connected || self.scores.contains_key(&p.id))
+            .map(|(p, _)| Score {
+                id: p.id,
+                name: p.name.clone(),
+                score: self.scores.get(&p.id).copied().unwrap_or_default(),
+            })
+            .collect::<Vec<_>>();
+        scores.sort_by(|s1, s2| s2.score.cmp(&s1.score).then_with(|| s1.name.cmp(&s2.name)));
+        scores
+    }
+
+    fn packet(&self, participants: &Participants) -> PacketOut {
+        PacketOut::Scoreboard {
+            scores: self.scores(participants),
+            correct_points: self.correct_points,
+            incorrect_points: self.incorrect_points,
+        }
+    }
+}
+
+enum RoomMessage {
+    ParticipantJoin {
+        name: Box<str>,
+        session: Option<Box<str>>,
+        team: Option<Box<str>>,
+        ip: IpAddr,
+        connection: Ulid,
+        reply: oneshot::Sender<Result<Arc<Participant>, Error>>,
+    },
+    SpectatorJoin(Ulid),
+    Heartbeat,
+    // Round trip time of a participant, measured by the heartbeat.
+    Latency(Ulid, Duration),
+    SetLatencyCompensation {
+        from: Ulid,
+        enabled: bool,
+    },
+    // Participant, reception instant, and reported round and elapsed time.
+    Buzzed(Arc<Participant>, Instant, Option<(Ulid, Duration)>),
+    SelectNext,
+    Clear,
+    SetArmed(bool),
+    StartTimer {
+        from: Ulid,
+        seconds: u64,
+    },
+    StopTimer,
+    TimerTick(Ulid),
+    SettlingWindowClosed(Ulid),
+    Judge(Verdict),
+    AdjustScore(Ulid, i64),
+    ResetScores,
+    ConfigureScoring {
+        correct_points: i64,
+        incorrect_points: i64,
+    },
+    CreateTeam(Box<str>),
+    RenameTeam(Ulid, Box<str>),
+    AssignTeam(Ulid, Option<Ulid>),
+    RenameParticipant(Ulid, Box<str>),
+    Kick(Ulid, Option<Box<str>>),
+    Ban {
+        participant: Ulid,
+        reason: Option<Box<str>>,
+        ip: bool,
+    },
+    // Either a participant or a host connection can be given the ownership.
+    TransferOwnership {
+        from: Ulid,
+        to: Ulid,
+    },
+    ParticipantLeft(Ulid, Ulid),
+    HostConnect(Box<WebSocket>, HostRole),
+    // A participant promoted to owner connected as a host, along with the
+    // co-host secret given to the previous owner.
+    PromotedConnect(Box<WebSocket>, Box<str>),
+    HostLeft(Ulid),
+    CloseRoom {
+        from: Ulid,
+        reason: Option<Box<str>>,
+    },
+    // Error answering an invalid packet of a host connection or a participant.
+    Rejected {
+        to: Ulid,
+        code: ErrorCode,
+        message: Box<str>,
+    },
+    HostGracePeriodExpired(Ulid),
+}
+
+impl RoomMessage {
+    /// Maps packets sent by the host connection to the room commands. Returns
+    /// `None` for packets the host isn't allowed to send.
+    fn from_host_packet(packet: PacketIn, connection: Ulid) -> Option<Self> {
+        Some(match packet {
+            PacketIn::SelectNext => RoomMessage::SelectNext,
+            PacketIn::Clear => RoomMessage::Clear,
+            PacketIn::Arm => RoomMessage::SetArmed(true),
+            PacketIn::Lock => RoomMessage::SetArmed(false),
+            PacketIn::StartTimer { seconds } => RoomMessage::StartTimer {
+                from: connection,
+                seconds,
+            },
+            PacketIn::StopTimer => RoomMessage::StopTimer,
+            PacketIn::Correct => RoomMessage::Judge(Verdict::Correct),
+            PacketIn::Incorrect => RoomMessage::Judge(Verdict::Incorrect),
+            PacketIn::AdjustScore { id, delta } => RoomMessage::AdjustScore(id, delta),
+            PacketIn::ResetScores => RoomMessage::ResetScores,
+            PacketIn::ConfigureScoring {
+                correct_points,
+                incorrect_points,
+            } => RoomMessage::ConfigureScoring {
+                correct_points,
+                incorrect_points,
+            },
+            PacketIn::CreateTeam { name } => RoomMessage::CreateTeam(name),
+            PacketIn::RenameTeam { id, name } => RoomMessage::RenameTeam(id, name),
+            PacketIn::AssignTeam { participant, team } => {
+                RoomMessage::AssignTeam(participant, team)
+            }
+            PacketIn::RenameParticipant { id, name } => RoomMessage::RenameParticipant(id, name),
+            PacketIn::Kick { id, reason } => RoomMessage::Kick(id, reason),
+            PacketIn::Ban { id, reason, ip } => RoomMessage::Ban {
+                participant: id,
+                reason,
+                ip,
+            },
+            PacketIn::TransferOwnership { id } => RoomMessage::TransferOwnership {
+                from: connection,
+                to: id,
+            },
+            PacketIn::SetLatencyCompensation { enabled } => RoomMessage::SetLatencyCompensation {
+                from: connection,
+                enabled,
+            },
+            PacketIn::CloseRoom { reason } => RoomMessage::CloseRoom {
+                from: connection,
+                reason,
+            },
+            PacketIn::Hello { .. } | PacketIn::Buzz { .. } => return None,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+enum BroadcastMessage {
+    All(WsMessage),
+    Single(Ulid, Arc<WsMessage>),
+    // Last message sent to a participant before closing its socket.
+    Kick(Ulid, Arc<WsMessage>),
+    // Copy of a packet sent to the host.
+    Spectators(WsMessage),
+}
+
+impl BroadcastMessage {
+    fn is_target(&self, id: &Ulid) -> bool {
+        match self {
+            BroadcastMessage::All(_) => true,
+            BroadcastMessage::Single(target_id, _) | BroadcastMessage::Kick(target_id, _) => {
+                target_id == id
+            }
+            BroadcastMessage::Spectators(_) => false,
+        }
+    }
+
+    fn is_spectator_target(&self, id: &Ulid) -> bool {
+        match self {
+            BroadcastMessage::All(_) | BroadcastMessage::Spectators(_) => true,
+            BroadcastMessage::Single(target_id, _) => target_id == id,
+            BroadcastMessage::Kick(..) => false,
+        }
+    }
+
+    fn inner(self) -> WsMessage {
+        match self {
+            BroadcastMessage::All(msg) | BroadcastMessage::Spectators(msg) => msg,
+            BroadcastMessage::Single(_, msg) | BroadcastMessage::Kick(_, msg) => (*msg).clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn participant(name: &str) -> Arc<Participant> {
+        Arc::new(Participant {
+            id: Ulid::new(),
+            name: name.into(),
+        })
+    }
+
+    fn order(run: &Run) -> Vec<Ulid> {
+        run.buzzed.iter().map(|b| b.participant.id).collect()
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn single_buzz_per_participant() {
+        let a = participant("a");
+        let start = Instant::now();
+        let mut run = Run::new(false);
+        run.buzz(Arc::clone(&a), None, start, None, None);
+        assert!(matches!(
+            run.buzz(Arc::clone(&a), None, start + ms(10), None, None),
+            BuzzResult::Already
+        ));
+        assert_eq!(order(&run), [a.id]);
+    }
+
+    #[test]
+    fn single_buzz_per_team() {
+        let (a, b, c) = (participant("a"), participant("b"), participant("c"));
+        let (team, other_team) = (Some(Ulid::new()), Some(Ulid::new()));
+        let start = Instant::now();
+        let mut run = Run::new(false);
+        run.buzz(Arc::clone(&a), team, start, None, None);
+        assert!(matches!(
+            run.buzz(Arc::clone(&b), team, start + ms(10), None, None),
+            BuzzResult::Already
+        ));
+        assert!(matches!(
+            run.buzz(Arc::clone(&c), other_team, start + ms(20), None, None),
+            BuzzResult::TimeDifference(20)
+        ));
+        assert_eq!(order(&run), [a.id, c.id]);
+    }
+
+    #[test]
+    fn participants_without_team_buzz_separately() {
+        let (a, b) = (participant("a"), participant("b"));
+        let start = Instant::now();
+        let mut run = Run::new(false);
+        run.buzz(Arc::clone(&a), None, start, None, None);
+        assert!(matches!(
+            run.buzz(Arc::clone(&b), None, start + ms(10), None, None),
+            BuzzResult::TimeDifference(10)
+        ));
+        assert_eq!(order(&run), [a.id, b.id]);
+    }
+
+    #[test]
+    fn buzzes_are_ordered_by_arrival_without_compensation() {
+        let (a, b) = (participant("a"), participant("b"));
+        let start = Instant::now();
+        let mut run = Run::new(false);
+        assert!(matches!(
+            run.buzz(Arc::clone(&a), None, start, Some(ms(0)), None),
+            BuzzResult::First
+        ));
+        assert!(matches!(
+            run.buzz(Arc::clone(&b), None, start + ms(10), Some(ms(100)), None),
+            BuzzResult::TimeDifference(10)
+        ));
+        assert_eq!(order(&run), [a.id, b.id]);
+        assert_eq!(run.selected(), Some(a.id));
+    }
+
+    #[test]
+    fn compensated_buzz_overtakes_selected() {
+        let (a, b) = (participant("a"), participant("b"));
+        let start = Instant::now();
+        let mut run = Run::new(true);
+        run.buzz(Arc::clone(&a), None, start, Some(ms(0)), None);
+        assert!(matches!(
+            run.buzz(Arc::clone(&b), None, start + ms(10), Some(ms(100)), None),
+            BuzzResult::Overtook(Some(overtaken)) if overtaken == a.id
+        ));
+        assert_eq!(order(&run), [b.id, a.id]);
+        assert_eq!(run.selected(), Some(b.id));
+    }
+
+    #[test]
+    fn compensation_is_capped() {
+        let (a, b) = (participant("a"), participant("b"));
+        let start = Instant::now();
+        let mut run = Run::new(true);
+        run.buzz(Arc::clone(&a), None, start + ms(1000), Some(ms(0)), None);
+        run.buzz(Arc::clone(&b), None, start + ms(1300), Some(ms(2000)), None);
+        assert_eq!(order(&run), [a.id, b.id]);
+    }
+
+    #[test]
+    fn judged_buzz_is_not_overtaken() {
+        let (a, b, c) = (participant("a"), participant("b"), participant("c"));
+        let start = Instant::now();
+        let mut run = Run::new(true);
+        run.buzz(Arc::clone(&a), None, start, Some(ms(0)), None);
+        run.buzz(Arc::clone(&b), None, start + ms(10), Some(ms(0)), None);
+        assert_eq!(run.judge_selected(Verdict::Incorrect), Some(a.id));
+        assert!(matches!(
+            run.buzz(Arc::clone(&c), None, start + ms(20), Some(ms(100)), None),
+            BuzzResult::Overtook(None)
+        ));
+        assert_eq!(order(&run), [a.id, c.id, b.id]);
+        assert_eq!(run.advance(), Some(c.id));
+    }
+
+    #[test]
+    fn enabling_compensation_reorders_waiting_buzzes() {
+        let (a, b, c) = (participant("a"), participant("b"), participant("c"));
+        let start = Instant::now();
+        let mut run = Run::new(false);
+        run.buzz(Arc::clone(&a), None, start, Some(ms(0)), None);
+        run.buzz(Arc::clone(&b), None, start + ms(20), Some(ms(0)), None);
+        run.buzz(Arc::clone(&c), None, start + ms(30), Some(ms(100)), None);
+        assert_eq!(order(&run), [a.id, b.id, c.id]);
+
+        run.set_compensated(true);
+        assert_eq!(order(&run), [a.id, c.id, b.id]);
+        run.set_compensated(false);
+        assert_eq!(order(&run), [a.id, b.id, c.id]);
+    }
+
+    #[test]
+    fn plausible_reported_time_is_accepted() {
+        let start = Instant::now();
+        assert_eq!(
+            reported_time(start, ms(500), ms(50), start + ms(600)),
+            Some(start + ms(550))
+        );
+        // Slightly late reception, within the tolerance.
+        assert_eq!(
+            reported_time(start, ms(500), ms(50), start + ms(640)),
+            Some(start + ms(550))
+        );
+    }
+
+    #[test]
+    fn reported_time_earlier_than_latency_is_rejected() {
+        let start = Instant::now();
+        assert_eq!(reported_time(start, ms(200), ms(50), start + ms(600)), None);
+        assert_eq!(reported_time(start, ms(0), ms(0), start + ms(1000)), None);
+    }
+
+    #[test]
+    fn reported_time_after_reception_is_rejected() {
+        let start = Instant::now();
+        assert_eq!(reported_time(start, ms(700), ms(50), start + ms(600)), None);
+        // Within the tolerance, the reception time is kept.
+        assert_eq!(
+            reported_time(start, ms(580), ms(50), start + ms(600)),
+            Some(start + ms(600))
+        );
+    }
+
+    #[test]
+    fn reported_time_overtakes_earlier_arrival() {
+        let (a, b) = (participant("a"), participant("b"));
+        let start = Instant::now();
+        let mut run = Run::new(false);
+        run.buzz(Arc::clone(&a), None, start + ms(600), None, None);
+        assert!(matches!(
+            run.buzz(
+                Arc::clone(&b),
+                None,
+                start + ms(620),
+                Some(ms(100)),
+                Some(start + ms(570)),
+            ),
+            BuzzResult::Overtook(Some(overtaken)) if overtaken == a.id
+        ));
+        assert_eq!(order(&run), [b.id, a.id]);
+        assert_eq!(run.time_difference(1), 30);
+    }
+
+    #[test]
+    fn taken_username_is_checked_against_the_session() {
+        let mut usernames = Usernames::new();
+        usernames.insert("Alice", "session");
+        assert!(usernames.check("alice", Some("session")).is_ok());
+        assert!(matches!(
+            usernames.check("ALICE", Some("other")),
+            Err(Error::UsernameTaken { suggestion }) if &*suggestion == "ALICE 2"
+        ));
+        assert!(usernames.check("Bob", None).is_ok());
+    }
+
+    #[test]
+    fn suggestion_skips_taken_names() {
+        let mut usernames = Usernames::new();
+        usernames.insert("Alice", "a");
+        usernames.insert("Alice 2", "b");
+        assert_eq!(&*usernames.suggest("Alice"), "Alice 3");
+    }
+
+    #[test]
+    fn suggestion_is_truncated_before_the_suffix() {
+        let name = "a".repeat(USERNAME_MAX_LEN);
+        let mut usernames = Usernames::new();
+        usernames.insert(&name, "a");
+        let suggestion = usernames.suggest(&name);
+        assert_eq!(suggestion.chars().count(), USERNAME_MAX_LEN);
+        assert_eq!(
+            &*suggestion,
+            format!("{} 2", "a".repeat(USERNAME_MAX_LEN - 2))
+        );
+    }
+
+    #[test]
+    fn resumed_session_keeps_the_username() {
+        let mut usernames = Usernames::new();
+        usernames.insert("Alice", "a");
+        usernames.insert("Alice", "b");
+        assert!(usernames.check("Alice", Some("a")).is_ok());
+        assert!(usernames.check("Alice", Some("b")).is_err());
+    }
+
+    #[test]
+    fn username_is_only_released_by_its_owner() {
+        let mut usernames = Usernames::new();
+        usernames.insert("Alice", "a");
+        usernames.remove("Alice", "b");
+        assert!(usernames.check("Alice", None).is_err());
+        usernames.remove("alice", "a");
+        assert!(usernames.check("Alice", None).is_ok());
+    }
+
+    #[test]
+    fn incorrect_answer_selects_the_next_buzz() {
+        let (a, b) = (participant("a"), participant("b"));
+        let start = Instant::now();
+        let mut run = Run::new(false);
+        run.buzz(Arc::clone(&a), None, start, None, None);
+        run.buzz(Arc::clone(&b), None, start + ms(10), None, None);
+        assert_eq!(run.judge_selected(Verdict::Incorrect), Some(a.id));
+        assert_eq!(run.judge_selected(Verdict::Correct), None);
+        assert_eq!(run.advance(), Some(b.id));
+        assert_eq!(run.judge_selected(Verdict::Correct), Some(b.id));
+        assert_eq!(run.winner(), Some(b.id));
+    }
+
+    #[test]
+    fn late_buzz_is_selected_once_the_list_ran_out() {
+        let (a, b) = (participant("a"), participant("b"));
+        let start = Instant::now();
+        let mut run = Run::new(false);
+        run.buzz(Arc::clone(&a), None, start, None, None);
+        run.judge_selected(Verdict::Incorrect);
+        assert_eq!(run.advance(), None);
+        assert_eq!(run.selected(), None);
+        run.buzz(Arc::clone(&b), None, start + ms(10), None, None);
+        assert_eq!(run.selected(), Some(b.id));
+        assert_eq!(run.judge_selected(Verdict::Correct), Some(b.id));
+    }
+
+    #[test]
+    fn winner_stops_the_selection() {
+        let (a, b) = (participant("a"), participant("b"));
+        let start = Instant::now();
+        let mut run = Run::new(false);
+        run.buzz(Arc::clone(&a), None, start, None, None);
+        run.buzz(Arc::clone(&b), None, start + ms(10), None, None);
+        assert_eq!(run.winner(), None);
+        run.judge_selected(Verdict::Correct);
+        assert_eq!(run.winner(), Some(a.id));
+        assert_eq!(run.select_next(), None);
+        assert_eq!(run.selected(), Some(a.id));
     }
 }
@@ -1,7 +1,88 @@
-pub fn sanitize(str: &str) -> &str {
-    str.trim()
+use rand::{distributions::Alphanumeric, Rng};
+use unicode_normalization::UnicodeNormalization;
+
+const TOKEN_LEN: usize = 32;
+
+/// Applies NFKC normalization, strips control and invisible characters and
+/// collapses whitespaces.
+pub fn sanitize(str: &str) -> Box<str> {
+    str.nfkc()
+        .map(|c| if c.is_whitespace() { ' ' } else { c })
+        .filter(|c| !is_invisible(*c))
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .into_boxed_str()
 }
 
+/// Maps names looking alike to the same key, using the Unicode confusable
+/// skeleton of the lowercased name.
 pub fn sanitize_for_search(str: &str) -> Box<str> {
-    str.trim().to_lowercase().into_boxed_str()
+    unicode_security::skeleton(&sanitize(str).to_lowercase())
+        .collect::<String>()
+        .to_lowercase()
+        .into_boxed_str()
+}
+
+fn is_invisible(c: char) -> bool {
+    c.is_control()
+        || matches!(
+            c,
+            '\u{00AD}'
+                | '\u{180E}'
+                | '\u{200B}'..='\u{200F}'
+                | '\u{202A}'..='\u{202E}'
+                | '\u{2060}'..='\u{2064}'
+                | '\u{2066}'..='\u{2069}'
+                | '\u{FEFF}'
+        )
+}
+
+pub fn generate_token() -> Box<str> {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LEN)
+        .map(char::from)
+        .collect::<String>()
+        .into_boxed_str()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn look_alike_names_share_key() {
+        let key = sanitize_for_search("Room 1");
+        assert_eq!(sanitize_for_search("\u{FF32}oom 1"), key);
+        assert_eq!(sanitize_for_search("R\u{043E}om 1"), key);
+        assert_eq!(sanitize_for_search("room 1"), key);
+        assert_eq!(sanitize_for_search("  Room \t 1 "), key);
+    }
+
+    #[test]
+    fn distinct_names_have_distinct_keys() {
+        assert_ne!(sanitize_for_search("Room 1"), sanitize_for_search("Room 2"));
+    }
+
+    #[test]
+    fn sanitize_applies_nfkc() {
+        assert_eq!(&*sanitize("\u{FF32}oom"), "Room");
+        assert_eq!(&*sanitize("e\u{0301}"), "\u{00E9}");
+    }
+
+    #[test]
+    fn sanitize_strips_invisible_characters() {
+        assert_eq!(&*sanitize("Ro\u{200B}om\u{FEFF}"), "Room");
+        assert_eq!(&*sanitize("\u{202E}Room\u{2066}"), "Room");
+        assert_eq!(&*sanitize("Ro\u{0007}om"), "Room");
+        assert_eq!(&*sanitize("\u{200B}"), "");
+    }
+
+    #[test]
+    fn sanitize_collapses_whitespaces() {
+        assert_eq!(&*sanitize("  Room \t\n 1  "), "Room 1");
+        assert_eq!(&*sanitize("Room\u{3000}1"), "Room 1");
+    }
 }